@@ -1,7 +1,11 @@
 use clap::Parser;
 use log::info;
 use prover::{
-    utils::{get_block_trace_from_file, init_env_and_log},
+    config::DRY_RUN,
+    utils::{
+        get_block_trace_from_file, init_env_and_log_with_cleanup, install_shutdown_handler,
+        prove_dry_run, shutdown, ProofManifest,
+    },
     zkevm::Prover,
 };
 use std::{fs, path::PathBuf, time::Instant};
@@ -22,27 +26,43 @@ struct Args {
 }
 
 fn main() {
-    init_env_and_log("prove");
+    // Remove the output dir if proving fails.
+    let mut output_dir = init_env_and_log_with_cleanup("prove");
+    install_shutdown_handler().expect("cannot install shutdown handler");
     std::env::set_var("VERIFY_CONFIG", "./prover/configs/verify_circuit.config");
 
     let args = Args::parse();
-    let mut prover = Prover::from_params_dir(&args.params_path);
 
-    let mut traces = Vec::new();
+    let mut trace_paths = Vec::new();
     let trace_path = PathBuf::from(&args.trace_path);
     if trace_path.is_dir() {
         for entry in fs::read_dir(trace_path).unwrap() {
             let path = entry.unwrap().path();
             if path.is_file() && path.to_str().unwrap().ends_with(".json") {
-                let block_trace = get_block_trace_from_file(path.to_str().unwrap());
-                traces.push(block_trace);
+                trace_paths.push(path);
             }
         }
     } else {
-        let block_trace = get_block_trace_from_file(trace_path.to_str().unwrap());
-        traces.push(block_trace);
+        trace_paths.push(trace_path);
     }
+    let traces: Vec<_> = trace_paths
+        .iter()
+        .map(|path| get_block_trace_from_file(path.to_str().unwrap()))
+        .collect();
+
+    // Record the input traces for audit.
+    ProofManifest::new(&trace_paths, &traces)
+        .and_then(|manifest| manifest.dump(output_dir.path()))
+        .expect("cannot write proof manifest");
 
+    if *DRY_RUN {
+        prove_dry_run(traces).expect("dry run failed");
+        output_dir.succeed();
+        shutdown();
+        return;
+    }
+
+    let mut prover = Prover::from_params_dir(&args.params_path);
     let mut proof_dir = PathBuf::from("proof_data");
 
     let now = Instant::now();
@@ -56,4 +76,6 @@ fn main() {
 
     fs::create_dir_all(&proof_dir).unwrap();
     chunk_proof.dump(&mut proof_dir, "chunk").unwrap();
+    output_dir.succeed();
+    shutdown();
 }
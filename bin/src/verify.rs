@@ -23,8 +23,9 @@ fn main() {
     let v = Verifier::from_params_dir(&args.params_path, Some(chunk_vk));
 
     let proof_path = PathBuf::from("proof_data").join("chunk_full_proof.json");
-    let proof_vec = read_from_file(&proof_path.to_string_lossy());
-    let proof = serde_json::from_slice::<Proof>(proof_vec.as_slice()).unwrap();
+    let proof = Proof::from_json_file(&proof_path.to_string_lossy())
+        .unwrap()
+        .expect("chunk proof file must exist");
     let verified = v.verify_chunk_proof(proof).is_ok();
     info!("verify agg proof: {}", verified)
 }
@@ -8,7 +8,48 @@ degree="${AGG_DEGREE:-${1:-25}}"
 params_dir="${2:-"./prover/test_params"}"
 mkdir -p "$params_dir"
 
+# Retry with exponential backoff (in seconds) for transient network errors.
+max_retries="${MAX_RETRIES:-5}"
+base_delay="${BASE_DELAY:-2}"
+
+# Optional expected sha256 of the params file. A mismatch is treated as a corrupt download.
+expected_sha256="${PARAMS_SHA256:-}"
+
+url=https://trusted-setup-halo2kzg.s3.eu-central-1.amazonaws.com/perpetual-powers-of-tau-raw-"$degree"
 output_file="$params_dir"/params"${degree}"
-rm -f "$output_file"
 
-axel -ac https://trusted-setup-halo2kzg.s3.eu-central-1.amazonaws.com/perpetual-powers-of-tau-raw-"$degree" -o "$output_file"
+# Fail fast if the params of this degree don't exist.
+status=$(curl -s -o /dev/null -I -w '%{http_code}' "$url" || true)
+if [ "$status" = "404" ]; then
+    echo "params of degree $degree not found: $url"
+    exit 1
+fi
+
+attempt=1
+delay="$base_delay"
+while true; do
+    echo "downloading params$degree, attempt $attempt/$max_retries"
+    rm -f "$output_file" "$output_file".st
+
+    if axel -ac "$url" -o "$output_file"; then
+        if [ -z "$expected_sha256" ]; then
+            break
+        fi
+
+        actual_sha256=$(sha256sum "$output_file" | cut -d ' ' -f 1)
+        if [ "$actual_sha256" = "$expected_sha256" ]; then
+            break
+        fi
+        echo "checksum mismatch of params$degree: expected $expected_sha256, got $actual_sha256"
+    fi
+
+    if [ "$attempt" -ge "$max_retries" ]; then
+        echo "failed to download params$degree after $attempt attempts"
+        rm -f "$output_file"
+        exit 1
+    fi
+
+    sleep "$delay"
+    attempt=$((attempt + 1))
+    delay=$((delay * 2))
+done
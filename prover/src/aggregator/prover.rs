@@ -1,4 +1,5 @@
 use crate::utils::{load_params, param_path_for_degree};
+use anyhow::{bail, Result};
 use halo2_proofs::{
     halo2curves::bn256::{Bn256, G1Affine},
     plonk::ProvingKey,
@@ -16,6 +17,15 @@ pub struct Prover {
     params_map: BTreeMap<u32, ParamsKZG<Bn256>>,
     // Cached id -> pk
     pk_map: HashMap<String, ProvingKey<G1Affine>>,
+    // Only set in lazy mode, params are loaded from this dir on first use.
+    lazy_params: Option<LazyParams>,
+}
+
+#[derive(Debug)]
+struct LazyParams {
+    params_dir: String,
+    // Used to downsize if params of a smaller degree doesn't exist.
+    max_degree: u32,
 }
 
 impl Prover {
@@ -23,9 +33,68 @@ impl Prover {
         Self {
             params_map,
             pk_map: HashMap::new(),
+            lazy_params: None,
         }
     }
 
+    /// Construct the prover in lazy mode, params of a degree are only loaded by the first proving
+    /// call which needs it. Since proving requires `&mut self`, the same degree could not be
+    /// loaded twice by simultaneous calls.
+    pub fn from_params_dir_lazy(params_dir: &str, degrees: &[u32]) -> Result<Self> {
+        let max_degree = match degrees.iter().max() {
+            Some(degree) => *degree,
+            None => bail!("No degrees of params to load lazily"),
+        };
+
+        Ok(Self {
+            params_map: BTreeMap::new(),
+            pk_map: HashMap::new(),
+            lazy_params: Some(LazyParams {
+                params_dir: params_dir.to_string(),
+                max_degree,
+            }),
+        })
+    }
+
+    // Load params of degree in lazy mode, downsize from the params of max degree if not exists.
+    // Return false if not in lazy mode.
+    pub(crate) fn load_lazy_params(&mut self, degree: u32) -> Result<bool> {
+        let lazy_params = match &self.lazy_params {
+            Some(lazy_params) => lazy_params,
+            None => return Ok(false),
+        };
+
+        let params = match load_params(&lazy_params.params_dir, degree, None) {
+            Ok(params) => params,
+            Err(e) => {
+                let max_degree = lazy_params.max_degree;
+                if max_degree <= degree {
+                    return Err(e);
+                }
+
+                // Load params of max degree to downsize, and cache them for other degrees.
+                if !self.params_map.contains_key(&max_degree) {
+                    let params =
+                        load_params(&lazy_params.params_dir, max_degree, None).map_err(|e| {
+                            anyhow::format_err!(
+                                "File `{}` must exist: {e}",
+                                param_path_for_degree(&lazy_params.params_dir, max_degree)
+                            )
+                        })?;
+                    self.params_map.insert(max_degree, params);
+                }
+
+                log::warn!("Optimization: download params{degree} to params dir");
+                let mut params = self.params_map[&max_degree].clone();
+                params.downsize(degree);
+                params
+            }
+        };
+
+        self.params_map.insert(degree, params);
+        Ok(true)
+    }
+
     pub fn from_params_dir(params_dir: &str, degrees: &[u32]) -> Self {
         let degrees = BTreeSet::from_iter(degrees);
         let max_degree = **degrees.last().unwrap();
@@ -61,6 +130,7 @@ impl Prover {
         Self {
             params_map,
             pk_map: HashMap::new(),
+            lazy_params: None,
         }
     }
 }
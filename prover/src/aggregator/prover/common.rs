@@ -1,13 +1,19 @@
 use super::Prover;
-use crate::{config::INNER_DEGREE, utils::tick, zkevm::circuit::TargetCircuit, Proof};
-use anyhow::Result;
+use crate::{
+    config::INNER_DEGREE,
+    utils::{phase_span, tick},
+    zkevm::circuit::TargetCircuit,
+    Proof,
+};
+use anyhow::{bail, Result};
 use halo2_proofs::{
-    halo2curves::bn256::{Bn256, Fr, G1Affine},
+    halo2curves::bn256::{Bn256, Fr, G1Affine, G2Affine},
     plonk::{keygen_pk2, Circuit, ProvingKey},
     poly::{commitment::Params, kzg::commitment::ParamsKZG},
 };
 use rand::Rng;
 use snark_verifier_sdk::{gen_evm_proof_shplonk, gen_pk, gen_snark_shplonk, CircuitExt, Snark};
+use std::mem::size_of;
 
 impl Prover {
     pub fn gen_snark<C: CircuitExt<Fr>>(
@@ -16,10 +22,11 @@ impl Prover {
         degree: u32,
         rng: &mut (impl Rng + Send),
         circuit: C,
-    ) -> Snark {
-        let (params, pk) = self.outer_params_and_pk(id, &circuit, degree);
+    ) -> Result<Snark> {
+        let (params, pk) = self.outer_params_and_pk(id, &circuit, degree)?;
 
-        gen_snark_shplonk(params, pk, circuit, rng, None::<&str>)
+        let _span = phase_span!("prove");
+        Ok(gen_snark_shplonk(params, pk, circuit, rng, None::<&str>))
     }
 
     pub fn gen_evm_proof<C: CircuitExt<Fr>>(
@@ -29,10 +36,11 @@ impl Prover {
         rng: &mut (impl Rng + Send),
         circuit: C,
     ) -> Result<Proof> {
-        let (params, pk) = self.outer_params_and_pk(id, &circuit, degree);
+        let (params, pk) = self.outer_params_and_pk(id, &circuit, degree)?;
 
         let instances = circuit.instances();
         let num_instance = circuit.num_instance();
+        let _span = phase_span!("prove");
         let proof = gen_evm_proof_shplonk(params, pk, circuit, instances.clone(), rng);
 
         Proof::new(pk, proof, &instances, Some(num_instance))
@@ -47,8 +55,9 @@ impl Prover {
 
         // Reuse pk.
         if !self.pk_map.contains_key(&id) {
+            let _span = phase_span!("keygen");
             tick(&format!("Before generate inner pk of {}", &id));
-            let pk = keygen_pk2(self.params(*INNER_DEGREE), circuit)?;
+            let pk = keygen_pk2(self.params(*INNER_DEGREE)?, circuit)?;
             tick(&format!("After generate inner pk of {}", &id));
 
             self.pk_map.insert(id.clone(), pk);
@@ -58,49 +67,76 @@ impl Prover {
         Ok((&self.params_map[&*INNER_DEGREE], &self.pk_map[&id]))
     }
 
-    pub fn params(&mut self, degree: u32) -> &ParamsKZG<Bn256> {
+    pub fn params(&mut self, degree: u32) -> Result<&ParamsKZG<Bn256>> {
         if self.params_map.contains_key(&degree) {
-            return &self.params_map[&degree];
+            return Ok(&self.params_map[&degree]);
+        }
+
+        if self.load_lazy_params(degree).map_err(|e| {
+            anyhow::format_err!("Failed to lazily load params of degree-{degree}: {e:#}")
+        })? {
+            log::info!("Lazily loaded params of degree-{degree}");
+            return Ok(&self.params_map[&degree]);
         }
 
         log::warn!("Optimization: download params{degree} to params dir");
 
         tick(&format!("Before generate params of {degree}"));
-        let mut new_params = self
-            .params_map
-            .range(degree..)
-            .next()
-            .unwrap_or_else(|| panic!("Must have params of degree-{degree}"))
-            .1
-            .clone();
+        let mut new_params = match self.params_map.range(degree..).next() {
+            Some((_, params)) => params.clone(),
+            None => bail!("Must have params of degree-{degree}"),
+        };
         new_params.downsize(degree);
         tick(&format!("After generate params of {degree}"));
 
         self.params_map.insert(degree, new_params);
-        &self.params_map[&degree]
+        Ok(&self.params_map[&degree])
     }
 
     pub fn pk(&self, id: &str) -> Option<&ProvingKey<G1Affine>> {
         self.pk_map.get(id)
     }
 
+    /// Degrees of the currently cached params, in ascending order.
+    pub fn loaded_degrees(&self) -> Vec<u32> {
+        self.params_map.keys().copied().collect()
+    }
+
+    /// Rough estimate of the memory (in bytes) held by the cached params and proving keys.
+    pub fn memory_estimate(&self) -> u64 {
+        // Each params has `g` and `g_lagrange` of `n` G1 points, and `g2`, `s_g2`.
+        let params_size: u64 = self
+            .params_map
+            .values()
+            .map(|p| 2 * p.n() * size_of::<G1Affine>() as u64 + 2 * size_of::<G2Affine>() as u64)
+            .sum();
+        let pk_size: u64 = self
+            .pk_map
+            .values()
+            .map(|pk| pk.bytes_length() as u64)
+            .sum();
+
+        params_size + pk_size
+    }
+
     pub fn outer_params_and_pk<C: Circuit<Fr>>(
         &mut self,
         id: &str,
         circuit: &C,
         degree: u32,
-    ) -> (&ParamsKZG<Bn256>, &ProvingKey<G1Affine>) {
+    ) -> Result<(&ParamsKZG<Bn256>, &ProvingKey<G1Affine>)> {
         // Reuse pk.
         if self.pk_map.contains_key(id) {
-            return (&self.params_map[&degree], &self.pk_map[id]);
+            return Ok((&self.params_map[&degree], &self.pk_map[id]));
         }
 
+        let _span = phase_span!("keygen");
         tick(&format!("Before generate outer pk of {}", &id));
-        let pk = gen_pk(self.params(degree), circuit, None);
+        let pk = gen_pk(self.params(degree)?, circuit, None);
         tick(&format!("After generate outer pk of {}", &id));
 
         self.pk_map.insert(id.to_string(), pk);
 
-        (&self.params_map[&degree], &self.pk_map[id])
+        Ok((&self.params_map[&degree], &self.pk_map[id]))
     }
 }
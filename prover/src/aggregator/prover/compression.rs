@@ -14,10 +14,10 @@ impl Prover {
         mut rng: impl Rng + Send,
         prev_snark: Snark,
     ) -> Result<Snark> {
-        let circuit = CompressionCircuit::new(self.params(degree), prev_snark, is_fresh, &mut rng)
+        let circuit = CompressionCircuit::new(self.params(degree)?, prev_snark, is_fresh, &mut rng)
             .map_err(|err| anyhow!("Failed to construct compression circuit: {err:?}"))?;
 
-        Ok(self.gen_snark(id, degree, &mut rng, circuit))
+        self.gen_snark(id, degree, &mut rng, circuit)
     }
 
     pub fn gen_comp_evm_proof(
@@ -28,7 +28,7 @@ impl Prover {
         mut rng: impl Rng + Send,
         prev_snark: Snark,
     ) -> Result<Proof> {
-        let circuit = CompressionCircuit::new(self.params(degree), prev_snark, is_fresh, &mut rng)
+        let circuit = CompressionCircuit::new(self.params(degree)?, prev_snark, is_fresh, &mut rng)
             .map_err(|err| anyhow!("Failed to construct compression circuit: {err:?}"))?;
 
         self.gen_evm_proof(id, degree, &mut rng, circuit)
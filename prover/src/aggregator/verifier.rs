@@ -1,4 +1,4 @@
-use crate::{utils::load_params, Proof};
+use crate::{io::deserialize_vk, utils::load_params, Proof};
 use aggregator::CompressionCircuit;
 use anyhow::Result;
 use halo2_proofs::{
@@ -9,7 +9,7 @@ use halo2_proofs::{
 };
 use snark_verifier::pcs::kzg::{Bdfg21, Kzg};
 use snark_verifier_sdk::{evm_verify, gen_evm_verifier, verify_snark_shplonk, CircuitExt};
-use std::{io::Cursor, path::Path};
+use std::path::Path;
 
 #[derive(Debug)]
 pub struct Verifier {
@@ -24,11 +24,7 @@ impl Verifier {
 
     pub fn from_params(params: ParamsKZG<Bn256>, raw_vk: Option<Vec<u8>>) -> Self {
         let vk = raw_vk.as_ref().map(|k| {
-            VerifyingKey::<G1Affine>::read::<_, CompressionCircuit>(
-                &mut Cursor::new(&k),
-                SerdeFormat::Processed,
-            )
-            .unwrap()
+            deserialize_vk::<CompressionCircuit>(k, Some(SerdeFormat::Processed)).unwrap()
         });
 
         Self { params, vk }
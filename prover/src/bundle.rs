@@ -0,0 +1,110 @@
+use crate::utils::{DEFAULT_SERDE_FORMAT, GIT_VERSION};
+use anyhow::{bail, Result};
+use halo2_proofs::{
+    halo2curves::bn256::Bn256,
+    poly::{commitment::Params, kzg::commitment::ParamsKZG},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+};
+
+// Verifier bundle layout:
+//   magic: 4 bytes
+//   version: 1 byte
+//   metadata len: 4 bytes (LE)
+//   metadata: JSON
+//   vk len: 8 bytes (LE)
+//   vk
+//   params: in `DEFAULT_SERDE_FORMAT`
+const BUNDLE_MAGIC: &[u8; 4] = b"SPVB";
+const BUNDLE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct VerifierBundleMetadata {
+    pub degree: u32,
+    pub git_version: String,
+}
+
+/// A single artifact for verifier deployment, which bundles params, vk and metadata.
+#[derive(Debug)]
+pub struct VerifierBundle {
+    pub metadata: VerifierBundleMetadata,
+    pub vk: Vec<u8>,
+    pub params: ParamsKZG<Bn256>,
+}
+
+pub fn write_verifier_bundle(file_path: &str, params: &ParamsKZG<Bn256>, vk: &[u8]) -> Result<()> {
+    let metadata = serde_json::to_vec(&VerifierBundleMetadata {
+        degree: params.k(),
+        git_version: GIT_VERSION.to_string(),
+    })?;
+
+    let mut writer = BufWriter::new(File::create(file_path)?);
+    writer.write_all(BUNDLE_MAGIC)?;
+    writer.write_all(&[BUNDLE_VERSION])?;
+    writer.write_all(&(metadata.len() as u32).to_le_bytes())?;
+    writer.write_all(&metadata)?;
+    writer.write_all(&(vk.len() as u64).to_le_bytes())?;
+    writer.write_all(vk)?;
+    params.write_custom(&mut writer, DEFAULT_SERDE_FORMAT)?;
+    writer.flush()?;
+
+    log::info!(
+        "wrote verifier bundle of degree {} to {file_path}",
+        params.k()
+    );
+    Ok(())
+}
+
+/// Read a verifier bundle. Refuse to load it if it's built by a different version, or the params
+/// mismatch the recorded degree.
+pub fn read_verifier_bundle(file_path: &str) -> Result<VerifierBundle> {
+    let mut reader = BufReader::new(File::open(file_path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != BUNDLE_MAGIC {
+        bail!("{file_path} is not a verifier bundle");
+    }
+
+    let mut version = [0u8; 1];
+    reader.read_exact(&mut version)?;
+    if version[0] != BUNDLE_VERSION {
+        bail!("unsupported verifier bundle version {}", version[0]);
+    }
+
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    let mut metadata = vec![0u8; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut metadata)?;
+    let metadata: VerifierBundleMetadata = serde_json::from_slice(&metadata)?;
+    if metadata.git_version != GIT_VERSION {
+        bail!(
+            "verifier bundle is built by version {}, but current version is {}",
+            metadata.git_version,
+            GIT_VERSION
+        );
+    }
+
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len)?;
+    let mut vk = vec![0u8; u64::from_le_bytes(len) as usize];
+    reader.read_exact(&mut vk)?;
+
+    let params = ParamsKZG::<Bn256>::read_custom(&mut reader, DEFAULT_SERDE_FORMAT)?;
+    if params.k() != metadata.degree {
+        bail!(
+            "verifier bundle params of degree {}, but metadata records degree {}",
+            params.k(),
+            metadata.degree
+        );
+    }
+
+    Ok(VerifierBundle {
+        metadata,
+        vk,
+        params,
+    })
+}
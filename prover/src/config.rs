@@ -1,4 +1,4 @@
-use crate::utils::read_env_var;
+use crate::utils::{read_env_flag, read_env_var};
 use once_cell::sync::Lazy;
 use std::collections::HashSet;
 
@@ -10,6 +10,25 @@ pub static AGG_LAYER2_DEGREE: Lazy<u32> = Lazy::new(|| read_env_var("AGG_LAYER2_
 pub static AGG_LAYER3_DEGREE: Lazy<u32> = Lazy::new(|| read_env_var("AGG_LAYER3_DEGREE", 25));
 pub static AGG_LAYER4_DEGREE: Lazy<u32> = Lazy::new(|| read_env_var("AGG_LAYER4_DEGREE", 25));
 
+// Calibration of `estimate_proving_time`: fixed cost in ms, and cost in ns of each row (of the used
+// rows and the domain).
+pub static PROVING_TIME_BASE_MS: Lazy<u64> =
+    Lazy::new(|| read_env_var("PROVING_TIME_BASE_MS", 30_000));
+pub static PROVING_TIME_PER_ROW_NS: Lazy<u64> =
+    Lazy::new(|| read_env_var("PROVING_TIME_PER_ROW_NS", 200_000));
+
+// Dir of the keccak input spills of `build_witness_spill_keccak`.
+pub static WITNESS_SPILL_DIR: Lazy<String> = Lazy::new(|| {
+    read_env_var(
+        "WITNESS_SPILL_DIR",
+        std::env::temp_dir().to_string_lossy().into_owned(),
+    )
+});
+
+// Stop after building the witness by `DRY_RUN=1`, e.g. a cheap CI gate of the trace to witness
+// conversion.
+pub static DRY_RUN: Lazy<bool> = Lazy::new(|| read_env_flag("DRY_RUN"));
+
 pub static ALL_AGG_DEGREES: Lazy<Vec<u32>> = Lazy::new(|| {
     Vec::from_iter(HashSet::from([
         *INNER_DEGREE,
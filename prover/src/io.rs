@@ -1,10 +1,15 @@
-use anyhow;
+use crate::utils::{resolve_serde_format, GIT_VERSION};
+use anyhow::{self, anyhow, bail};
 use halo2_proofs::{
-    halo2curves::bn256::{Fq, Fr, G1Affine},
-    plonk::VerifyingKey,
+    halo2curves::{
+        bn256::{Fq, Fr, G1Affine},
+        group::GroupEncoding,
+    },
+    plonk::{Circuit, ProvingKey, VerifyingKey},
     SerdeFormat,
 };
 use num_bigint::BigUint;
+use once_cell::sync::Lazy;
 use snark_verifier::util::arithmetic::PrimeField;
 use snark_verifier_sdk::Snark;
 use std::{
@@ -88,6 +93,104 @@ pub fn serialize_vk(vk: &VerifyingKey<G1Affine>) -> Vec<u8> {
     result
 }
 
+/// Readable structure of the vk for inspection and diffing across versions, not for re-loading as
+/// `serialize_vk`. Points are hex of the compressed encoding.
+pub fn vk_to_json(vk: &VerifyingKey<G1Affine>) -> serde_json::Value {
+    let points = |points: &[G1Affine]| -> Vec<String> {
+        points.iter().map(|p| hex::encode(p.to_bytes())).collect()
+    };
+    let domain = vk.get_domain();
+    let cs = vk.cs();
+
+    serde_json::json!({
+        "domain": {
+            "k": domain.k(),
+            "extended_k": domain.extended_k(),
+            "omega": format!("{:?}", domain.get_omega()),
+        },
+        "num_fixed_columns": cs.num_fixed_columns(),
+        "num_advice_columns": cs.num_advice_columns(),
+        "num_instance_columns": cs.num_instance_columns(),
+        "fixed_commitments": points(vk.fixed_commitments()),
+        "permutation_commitments": points(vk.permutation().commitments()),
+        "transcript_repr": format!("{:?}", vk.transcript_repr()),
+    })
+}
+
+/// Bytes of a G1 point in the serde format.
+pub fn g1_point_len(serde_fmt: SerdeFormat) -> usize {
+    match serde_fmt {
+        SerdeFormat::Processed => 32,
+        SerdeFormat::RawBytes | SerdeFormat::RawBytesUnchecked => 64,
+    }
+}
+
+/// Serialize vk in the serde format, default as `resolve_serde_format`.
+/// Notice `serialize_vk` is always `Processed`, as the vk embedded in proofs.
+pub fn serialize_vk_with_format(
+    vk: &VerifyingKey<G1Affine>,
+    serde_fmt: Option<SerdeFormat>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut result = Vec::<u8>::new();
+    vk.write(&mut result, serde_fmt.unwrap_or_else(resolve_serde_format))?;
+    Ok(result)
+}
+
+pub fn deserialize_vk<C: Circuit<Fr>>(
+    buf: &[u8],
+    serde_fmt: Option<SerdeFormat>,
+) -> anyhow::Result<VerifyingKey<G1Affine>> {
+    let serde_fmt = serde_fmt.unwrap_or_else(resolve_serde_format);
+    check_vk_len(buf, serde_fmt)?;
+
+    let vk = VerifyingKey::<G1Affine>::read::<_, C>(&mut Cursor::new(buf), serde_fmt)?;
+    Ok(vk)
+}
+
+// Check the vk is long enough for its fixed commitments in the serde format, to catch mixing the
+// formats early. Layout of vk header: k (u32 BE), number of fixed commitments (u32 BE).
+fn check_vk_len(buf: &[u8], serde_fmt: SerdeFormat) -> anyhow::Result<()> {
+    if buf.len() < 8 {
+        bail!("vk of {} bytes is too short", buf.len());
+    }
+
+    let num_fixed_commitments = u32::from_be_bytes(buf[4..8].try_into().unwrap()) as usize;
+    let expected_len = 8 + num_fixed_commitments * g1_point_len(serde_fmt);
+    if buf.len() < expected_len {
+        bail!(
+            "vk of {} bytes, but {} fixed commitments need {} bytes in format {:?}",
+            buf.len(),
+            num_fixed_commitments,
+            expected_len,
+            serde_fmt
+        );
+    }
+
+    Ok(())
+}
+
+/// Serialize pk in the serde format, default as `resolve_serde_format`.
+pub fn serialize_pk(
+    pk: &ProvingKey<G1Affine>,
+    serde_fmt: Option<SerdeFormat>,
+) -> anyhow::Result<Vec<u8>> {
+    let mut result = Vec::<u8>::new();
+    pk.write(&mut result, serde_fmt.unwrap_or_else(resolve_serde_format))?;
+    Ok(result)
+}
+
+pub fn deserialize_pk<C: Circuit<Fr>>(
+    buf: &[u8],
+    serde_fmt: Option<SerdeFormat>,
+) -> anyhow::Result<ProvingKey<G1Affine>> {
+    let serde_fmt = serde_fmt.unwrap_or_else(resolve_serde_format);
+    // pk starts with its vk.
+    check_vk_len(buf, serde_fmt)?;
+
+    let pk = ProvingKey::<G1Affine>::read::<_, C>(&mut Cursor::new(buf), serde_fmt)?;
+    Ok(pk)
+}
+
 pub fn write_verify_circuit_vk(folder: &mut PathBuf, verify_circuit_vk: &[u8]) {
     folder.push("verify_circuit.vkey");
     let mut fd = std::fs::File::create(folder.as_path()).unwrap();
@@ -136,8 +239,12 @@ pub fn serialize_verify_circuit_final_pair(pair: &(G1Affine, G1Affine, Vec<Fr>))
 }
 
 pub fn write_snark(file_path: &str, snark: &Snark) {
-    let mut fd = std::fs::File::create(file_path).unwrap();
-    serde_json::to_writer_pretty(&mut fd, snark).unwrap()
+    try_write_snark(file_path, snark).unwrap()
+}
+
+pub fn try_write_snark(file_path: &str, snark: &Snark) -> anyhow::Result<()> {
+    let buf = serde_json::to_vec_pretty(snark)?;
+    write_artifact(file_path, &buf)
 }
 
 pub fn load_snark(file_path: &str) -> anyhow::Result<Option<Snark>> {
@@ -145,8 +252,8 @@ pub fn load_snark(file_path: &str) -> anyhow::Result<Option<Snark>> {
         return Ok(None);
     }
 
-    let fd = File::open(file_path)?;
-    let mut deserializer = serde_json::Deserializer::from_reader(fd);
+    let buf = read_artifact(file_path)?;
+    let mut deserializer = serde_json::Deserializer::from_slice(&buf);
     deserializer.disable_recursion_limit();
     let deserializer = serde_stacker::Deserializer::new(&mut deserializer);
     let snark = serde::Deserialize::deserialize(deserializer)?;
@@ -180,3 +287,201 @@ pub fn load_instances_flat(buf: &[u8]) -> Vec<Vec<Vec<Fr>>> {
 
     vec![vec![ret]]
 }
+
+// Hex encoded 32-byte key. If set, cached artifacts are encrypted with AES-256-GCM.
+static ARTIFACT_KEY: Lazy<Option<String>> =
+    Lazy::new(|| std::env::var("ARTIFACT_KEY").ok().filter(|k| !k.is_empty()));
+
+// Encrypted artifact layout:
+//   magic: 4 bytes
+//   version: 1 byte
+//   nonce: 12 bytes
+//   ciphertext (with 16 bytes tag)
+pub(crate) const ARTIFACT_MAGIC: &[u8; 4] = b"SPEA";
+
+fn artifact_key() -> anyhow::Result<Option<Vec<u8>>> {
+    ARTIFACT_KEY
+        .as_ref()
+        .map(|k| {
+            hex::decode(k.trim_start_matches("0x"))
+                .map_err(|e| anyhow!("ARTIFACT_KEY must be hex encoded: {e}"))
+        })
+        .transpose()
+}
+
+/// Whether artifacts are encrypted, i.e. `ARTIFACT_KEY` is set.
+pub fn artifact_encryption_enabled() -> anyhow::Result<bool> {
+    Ok(artifact_key()?.is_some())
+}
+
+/// Encrypt the artifact if `ARTIFACT_KEY` is set, otherwise return it as is.
+pub fn encrypt_artifact(buf: &[u8]) -> anyhow::Result<Vec<u8>> {
+    match artifact_key()? {
+        Some(key) => artifact_cipher::encrypt(&key, buf),
+        None => Ok(buf.to_vec()),
+    }
+}
+
+/// Decrypt the artifact if it has an encryption header, otherwise return it as is.
+pub fn decrypt_artifact(buf: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    if !buf.starts_with(ARTIFACT_MAGIC) {
+        return Ok(buf);
+    }
+
+    match artifact_key()? {
+        Some(key) => artifact_cipher::decrypt(&key, &buf),
+        None => bail!("artifact is encrypted, but ARTIFACT_KEY is not set"),
+    }
+}
+
+pub fn write_artifact<P: AsRef<Path>>(file_path: P, buf: &[u8]) -> anyhow::Result<()> {
+    let buf = encrypt_artifact(buf)?;
+    let mut fd = File::create(file_path)?;
+    fd.write_all(&buf)?;
+
+    Ok(())
+}
+
+pub fn read_artifact<P: AsRef<Path>>(file_path: P) -> anyhow::Result<Vec<u8>> {
+    let mut buf = vec![];
+    File::open(file_path)?.read_to_end(&mut buf)?;
+
+    decrypt_artifact(buf)
+}
+
+#[cfg(feature = "encrypt-artifacts")]
+mod artifact_cipher {
+    use super::ARTIFACT_MAGIC;
+    use aes_gcm::{
+        aead::{Aead, AeadCore, KeyInit, OsRng},
+        Aes256Gcm, Nonce,
+    };
+    use anyhow::{anyhow, bail, Result};
+
+    const ARTIFACT_VERSION: u8 = 1;
+    const NONCE_LEN: usize = 12;
+    const HEADER_LEN: usize = ARTIFACT_MAGIC.len() + 1 + NONCE_LEN;
+
+    pub(super) fn encrypt(key: &[u8], buf: &[u8]) -> Result<Vec<u8>> {
+        let cipher =
+            Aes256Gcm::new_from_slice(key).map_err(|e| anyhow!("invalid ARTIFACT_KEY: {e}"))?;
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(&nonce, buf)
+            .map_err(|e| anyhow!("failed to encrypt artifact: {e}"))?;
+
+        let mut result = Vec::with_capacity(HEADER_LEN + ciphertext.len());
+        result.extend_from_slice(ARTIFACT_MAGIC);
+        result.push(ARTIFACT_VERSION);
+        result.extend_from_slice(&nonce);
+        result.extend(ciphertext);
+
+        Ok(result)
+    }
+
+    pub(super) fn decrypt(key: &[u8], buf: &[u8]) -> Result<Vec<u8>> {
+        if buf.len() < HEADER_LEN {
+            bail!("encrypted artifact is truncated");
+        }
+
+        let version = buf[ARTIFACT_MAGIC.len()];
+        if version != ARTIFACT_VERSION {
+            bail!("unsupported encrypted artifact version {version}");
+        }
+
+        let (nonce, ciphertext) = buf[ARTIFACT_MAGIC.len() + 1..].split_at(NONCE_LEN);
+        let cipher =
+            Aes256Gcm::new_from_slice(key).map_err(|e| anyhow!("invalid ARTIFACT_KEY: {e}"))?;
+
+        cipher
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|e| anyhow!("failed to decrypt artifact: {e}"))
+    }
+}
+
+#[cfg(not(feature = "encrypt-artifacts"))]
+mod artifact_cipher {
+    use anyhow::{bail, Result};
+
+    pub(super) fn encrypt(_key: &[u8], _buf: &[u8]) -> Result<Vec<u8>> {
+        bail!("ARTIFACT_KEY is set, but feature `encrypt-artifacts` is disabled")
+    }
+
+    pub(super) fn decrypt(_key: &[u8], _buf: &[u8]) -> Result<Vec<u8>> {
+        bail!("artifact is encrypted, but feature `encrypt-artifacts` is disabled")
+    }
+}
+
+// Witness cache layout:
+//   magic: 4 bytes
+//   version: 1 byte
+//   git version len: 1 byte
+//   git version
+//   payload
+const WITNESS_CACHE_MAGIC: &[u8; 4] = b"SPWC";
+const WITNESS_CACHE_VERSION: u8 = 1;
+
+/// Error of a witness cache built by a different format or prover version. The caller should
+/// rebuild the witness and overwrite the cache.
+#[derive(Debug)]
+pub struct StaleCache(pub String);
+
+impl std::fmt::Display for StaleCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "stale witness cache: {}", self.0)
+    }
+}
+
+impl std::error::Error for StaleCache {}
+
+// Git version recorded in cache header, truncated to fit the 1-byte length.
+fn cache_git_version() -> &'static [u8] {
+    let git_version = GIT_VERSION.as_bytes();
+    &git_version[..git_version.len().min(u8::MAX as usize)]
+}
+
+pub fn write_witness_cache<P: AsRef<Path>>(file_path: P, payload: &[u8]) -> anyhow::Result<()> {
+    let git_version = cache_git_version();
+
+    let mut buf =
+        Vec::with_capacity(WITNESS_CACHE_MAGIC.len() + 2 + git_version.len() + payload.len());
+    buf.extend_from_slice(WITNESS_CACHE_MAGIC);
+    buf.push(WITNESS_CACHE_VERSION);
+    buf.push(git_version.len() as u8);
+    buf.extend_from_slice(git_version);
+    buf.extend_from_slice(payload);
+
+    write_artifact(file_path, &buf)
+}
+
+/// Read the payload of a witness cache. Return `StaleCache` error if magic, format version or
+/// git version mismatches.
+pub fn read_witness_cache<P: AsRef<Path>>(file_path: P) -> anyhow::Result<Vec<u8>> {
+    let buf = read_artifact(file_path)?;
+
+    if !buf.starts_with(WITNESS_CACHE_MAGIC) {
+        return Err(StaleCache("invalid magic".to_string()).into());
+    }
+    let header = &buf[WITNESS_CACHE_MAGIC.len()..];
+    if header.len() < 2 {
+        return Err(StaleCache("truncated header".to_string()).into());
+    }
+    if header[0] != WITNESS_CACHE_VERSION {
+        return Err(StaleCache(format!("unsupported format version {}", header[0])).into());
+    }
+
+    let git_version_len = header[1] as usize;
+    let git_version = header
+        .get(2..2 + git_version_len)
+        .ok_or_else(|| StaleCache("truncated header".to_string()))?;
+    if git_version != cache_git_version() {
+        return Err(StaleCache(format!(
+            "built by version {}, but current version is {}",
+            String::from_utf8_lossy(git_version),
+            GIT_VERSION
+        ))
+        .into());
+    }
+
+    Ok(header[2 + git_version_len..].to_vec())
+}
@@ -1,9 +1,11 @@
 pub mod aggregator;
+pub mod bundle;
 pub mod config;
 mod evm_verifier;
 pub mod io;
 pub mod proof;
 pub mod test_util;
+pub mod transcript;
 pub mod utils;
 pub mod zkevm;
 
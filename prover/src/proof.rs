@@ -1,22 +1,37 @@
-use crate::io::{deserialize_fr_matrix, serialize_fr_matrix, serialize_vk, write_file};
-use anyhow::Result;
+use crate::{
+    io::{
+        artifact_encryption_enabled, decrypt_artifact, deserialize_fr_matrix, encrypt_artifact,
+        read_artifact, serialize_fr_matrix, serialize_vk, write_artifact, write_file,
+        ARTIFACT_MAGIC,
+    },
+    utils::GIT_VERSION,
+};
+use anyhow::{anyhow, bail, Result};
+use chrono::Utc;
 use halo2_proofs::{
-    halo2curves::bn256::{Fr, G1Affine},
-    plonk::ProvingKey,
+    halo2curves::{
+        bn256::{Fr, G1Affine},
+        group::GroupEncoding,
+        serde::SerdeObject,
+        CurveAffine,
+    },
+    plonk::{ProvingKey, VerifyingKey},
     SerdeFormat,
 };
 use serde_derive::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use snark_verifier::{
     util::{
-        arithmetic::Domain,
+        arithmetic::{Domain, PrimeField},
         protocol::{Expression, QuotientPolynomial},
     },
     Protocol,
 };
 use snark_verifier_sdk::Snark;
 use std::{
-    fs::File,
+    io::{Read, Write},
     path::{Path, PathBuf},
+    time::Duration,
 };
 use types::base64;
 
@@ -30,6 +45,16 @@ pub struct Proof {
     instances: Vec<u8>,
     // Only for EVM proof.
     num_instance: Option<Vec<usize>>,
+    // Hex of `circuit_fingerprint`, None for the proofs generated before it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    circuit_fingerprint: Option<String>,
+}
+
+/// Hash of the circuit by its vk, which commits to the fixed columns and the params of proving.
+pub type CircuitFingerprint = [u8; 32];
+
+pub fn circuit_fingerprint(vk: &VerifyingKey<G1Affine>) -> CircuitFingerprint {
+    Sha256::digest(serialize_vk(vk)).into()
 }
 
 impl Proof {
@@ -47,6 +72,7 @@ impl Proof {
             vk,
             instances,
             num_instance,
+            circuit_fingerprint: Some(hex::encode(circuit_fingerprint(pk.get_vk()))),
         })
     }
 
@@ -55,8 +81,8 @@ impl Proof {
             return Ok(None);
         }
 
-        let fd = File::open(file_path)?;
-        let mut deserializer = serde_json::Deserializer::from_reader(fd);
+        let buf = read_artifact(file_path)?;
+        let mut deserializer = serde_json::Deserializer::from_slice(&buf);
         deserializer.disable_recursion_limit();
         let deserializer = serde_stacker::Deserializer::new(&mut deserializer);
         let proof = serde::Deserialize::deserialize(deserializer)?;
@@ -70,9 +96,9 @@ impl Proof {
         write_file(dir, &format!("{name}_instances.data"), &self.instances);
 
         dir.push(format!("{name}_full_proof.json"));
-        let mut fd = std::fs::File::create(dir.as_path())?;
+        let result = write_artifact(dir.as_path(), &serde_json::to_vec_pretty(&self)?);
         dir.pop();
-        serde_json::to_writer_pretty(&mut fd, &self)?;
+        result?;
 
         Ok(())
     }
@@ -89,6 +115,7 @@ impl Proof {
             vk,
             instances,
             num_instance: None,
+            circuit_fingerprint: Some(hex::encode(circuit_fingerprint(pk.get_vk()))),
         })
     }
 
@@ -113,6 +140,375 @@ impl Proof {
     pub fn num_instance(&self) -> Option<&Vec<usize>> {
         self.num_instance.as_ref()
     }
+
+    /// Hex of the fingerprint of the circuit which generated the proof.
+    pub fn circuit_fingerprint(&self) -> Option<&str> {
+        self.circuit_fingerprint.as_deref()
+    }
+}
+
+/// Serialize the proof as `{name}_full_proof.json` directly into the writer, e.g. a
+/// `BufWriter<File>`, without an intermediate buffer of the whole proof. The vk is kept as
+/// `Processed` by `serialize_vk`, and its points are checked before writing.
+///
+/// Same as `Proof::dump`, the proof is encrypted if `ARTIFACT_KEY` is set. The encryption needs the
+/// whole proof, so it's buffered in that case.
+pub fn write_proof_to<W: Write>(proof: &Proof, mut writer: W) -> Result<()> {
+    check_vk_points(&proof.vk, SerdeFormat::Processed)?;
+
+    if artifact_encryption_enabled()? {
+        writer.write_all(&encrypt_artifact(&serde_json::to_vec(proof)?)?)?;
+    } else {
+        serde_json::to_writer(&mut writer, proof)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// Deserialize a proof written by `write_proof_to` from the reader, and check the vk points. An
+/// encrypted proof is read as a whole and decrypted by `ARTIFACT_KEY`.
+pub fn read_proof_from<R: Read>(mut reader: R) -> Result<Proof> {
+    let mut head = Vec::with_capacity(ARTIFACT_MAGIC.len());
+    reader
+        .by_ref()
+        .take(ARTIFACT_MAGIC.len() as u64)
+        .read_to_end(&mut head)?;
+
+    let proof = if head == ARTIFACT_MAGIC {
+        let mut buf = head;
+        reader.read_to_end(&mut buf)?;
+        deserialize_proof(decrypt_artifact(buf)?.as_slice())?
+    } else {
+        deserialize_proof(head.as_slice().chain(reader))?
+    };
+    check_vk_points(&proof.vk, SerdeFormat::Processed)?;
+
+    Ok(proof)
+}
+
+fn deserialize_proof<R: Read>(reader: R) -> Result<Proof> {
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    deserializer.disable_recursion_limit();
+    let deserializer = serde_stacker::Deserializer::new(&mut deserializer);
+
+    Ok(serde::Deserialize::deserialize(deserializer)?)
+}
+
+/// Compare the proof transcripts and the decoded public instances, so proofs differing only in
+/// the serialization of instances are still equal. Useful to check proving is deterministic.
+pub fn proofs_equal(a: &Proof, b: &Proof) -> bool {
+    a.proof == b.proof && a.instances() == b.instances()
+}
+
+// Instance layout of a chunk proof (by compression circuit) consumed by the aggregation circuit:
+// the accumulator of `ACC_LEN` limbs, then each byte of the public input hash as a field element.
+pub const CHUNK_ACC_LEN: usize = 12;
+pub const CHUNK_DIGEST_LEN: usize = 32;
+
+/// Decoded public instances of a chunk proof.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkInstances {
+    pub accumulator: Vec<Fr>,
+    pub pi_hash: [u8; CHUNK_DIGEST_LEN],
+}
+
+impl ChunkInstances {
+    /// The canonical instance vector of the aggregation circuit input.
+    pub fn encode(&self) -> Vec<Fr> {
+        self.accumulator
+            .iter()
+            .copied()
+            .chain(self.pi_hash.iter().map(|b| Fr::from(*b as u64)))
+            .collect()
+    }
+}
+
+/// Encode the instances of a chunk proof in the layout the aggregation circuit consumes. Return
+/// error if the proof instances aren't of the layout.
+pub fn encode_chunk_instances(proof: &Proof) -> Result<Vec<Fr>> {
+    let instances = proof.instances();
+    if instances.len() != 1 {
+        bail!("chunk proof of {} instance columns", instances.len());
+    }
+
+    Ok(decode_chunk_instances(&instances[0])?.encode())
+}
+
+pub fn decode_chunk_instances(instances: &[Fr]) -> Result<ChunkInstances> {
+    if instances.len() != CHUNK_ACC_LEN + CHUNK_DIGEST_LEN {
+        bail!(
+            "chunk instances of len {}, expected {}",
+            instances.len(),
+            CHUNK_ACC_LEN + CHUNK_DIGEST_LEN
+        );
+    }
+
+    let mut pi_hash = [0u8; CHUNK_DIGEST_LEN];
+    for (i, (byte, f)) in pi_hash
+        .iter_mut()
+        .zip(instances[CHUNK_ACC_LEN..].iter())
+        .enumerate()
+    {
+        let repr = f.to_repr();
+        if repr[1..].iter().any(|b| *b != 0) {
+            bail!("byte {i} of chunk pi hash is not a byte: {f:?}");
+        }
+        *byte = repr[0];
+    }
+
+    Ok(ChunkInstances {
+        accumulator: instances[..CHUNK_ACC_LEN].to_vec(),
+        pi_hash,
+    })
+}
+
+/// Check the proof is generated by a circuit of the vk before the expensive verification, e.g. to
+/// catch the version skew of prover and verifier: the circuit fingerprint (if recorded) and the
+/// instance columns must match, and the vk embedded in proof (if any) must be the same.
+pub fn check_proof_vk_compatible(proof: &Proof, vk: &VerifyingKey<G1Affine>) -> Result<()> {
+    if let Some(fingerprint) = proof.circuit_fingerprint() {
+        let expected = hex::encode(circuit_fingerprint(vk));
+        if fingerprint != expected {
+            bail!("proof is generated by circuit {fingerprint}, but the verifier circuit is {expected}");
+        }
+    }
+
+    let num_instance_columns = vk.cs().num_instance_columns();
+    let instances = proof.instances();
+    if instances.len() != num_instance_columns {
+        bail!(
+            "proof has {} instance columns, but vk expects {num_instance_columns}",
+            instances.len()
+        );
+    }
+
+    if proof.vk.is_empty() {
+        return Ok(());
+    }
+    let vk_bytes = serialize_vk(vk);
+    if proof.vk.len() != vk_bytes.len() {
+        bail!(
+            "proof is generated by a vk of {} bytes, but vk of {} bytes ({} fixed and {} permutation commitments) is given",
+            proof.vk.len(),
+            vk_bytes.len(),
+            vk.fixed_commitments().len(),
+            vk.permutation().commitments().len()
+        );
+    }
+    if proof.vk != vk_bytes {
+        bail!("proof is generated by a different vk of the same shape, check the prover version");
+    }
+
+    Ok(())
+}
+
+/// Structure of a proof file, parsed without params.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ProofShape {
+    pub degree: u32,
+    pub num_fixed_commitments: usize,
+    pub num_instance: Vec<usize>,
+    pub proof_size: usize,
+    pub is_evm: bool,
+}
+
+/// Check a proof file (`*_full_proof.json`) is well-formed without params or pairing check: the
+/// instances are valid field elements, and the vk commitments are on curve. Like `load_params`,
+/// `serde_fmt` is of the vk points, and defaults to `Processed` as `serialize_vk`.
+pub fn validate_proof_format(
+    file_path: &str,
+    serde_fmt: Option<SerdeFormat>,
+) -> Result<ProofShape> {
+    let serde_fmt = serde_fmt.unwrap_or(SerdeFormat::Processed);
+    let proof =
+        Proof::from_json_file(file_path)?.ok_or_else(|| anyhow!("{file_path} doesn't exist"))?;
+
+    if proof.proof.is_empty() {
+        bail!("empty proof in {file_path}");
+    }
+    let is_evm = proof.num_instance.is_some();
+    // EVM proof consists of 32-byte words.
+    if is_evm && proof.proof.len() % 32 != 0 {
+        bail!("invalid EVM proof size {}", proof.proof.len());
+    }
+
+    let instances: Vec<Vec<Vec<u8>>> = serde_json::from_slice(&proof.instances)?;
+    for (i, column) in instances.iter().enumerate() {
+        for (j, buf) in column.iter().enumerate() {
+            let repr: [u8; 32] = buf
+                .as_slice()
+                .try_into()
+                .map_err(|_| anyhow!("instance [{i}][{j}] of {} bytes", buf.len()))?;
+            if Option::<Fr>::from(Fr::from_repr(repr)).is_none() {
+                bail!("instance [{i}][{j}] is not a field element");
+            }
+        }
+    }
+    let num_instance: Vec<usize> = instances.iter().map(Vec::len).collect();
+    if let Some(expected) = &proof.num_instance {
+        if expected != &num_instance {
+            bail!("num_instance {expected:?}, but instances of {num_instance:?}");
+        }
+    }
+
+    let (degree, num_fixed_commitments) = check_vk_points(&proof.vk, serde_fmt)?;
+
+    let shape = ProofShape {
+        degree,
+        num_fixed_commitments,
+        num_instance,
+        proof_size: proof.proof.len(),
+        is_evm,
+    };
+    log::debug!("proof shape of {file_path}: {shape:?}");
+    Ok(shape)
+}
+
+// Check the fixed commitments in the vk header are on curve, and return the degree and the number
+// of fixed commitments.
+fn check_vk_points(vk: &[u8], serde_fmt: SerdeFormat) -> Result<(u32, usize)> {
+    // Layout of vk header: k (u32 BE), number of fixed commitments (u32 BE), fixed commitments.
+    let mut reader = vk;
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    let degree = u32::from_be_bytes(buf);
+    reader.read_exact(&mut buf)?;
+    let num_fixed_commitments = u32::from_be_bytes(buf) as usize;
+    for i in 0..num_fixed_commitments {
+        read_g1_checked(&mut reader, serde_fmt)
+            .map_err(|e| anyhow!("fixed commitment {i} of vk: {e}"))?;
+    }
+
+    Ok((degree, num_fixed_commitments))
+}
+
+// Read a G1 point and check it's on curve, whatever the serde format.
+fn read_g1_checked<R: Read>(reader: &mut R, serde_fmt: SerdeFormat) -> Result<G1Affine> {
+    let point = match serde_fmt {
+        SerdeFormat::Processed => {
+            let mut repr = <G1Affine as GroupEncoding>::Repr::default();
+            reader.read_exact(repr.as_mut())?;
+            Option::from(G1Affine::from_bytes(&repr)).ok_or_else(|| anyhow!("invalid point"))?
+        }
+        // Raw x and y coordinates.
+        SerdeFormat::RawBytes | SerdeFormat::RawBytesUnchecked => {
+            let mut buf = [0u8; 64];
+            reader.read_exact(&mut buf)?;
+            G1Affine::from_raw_bytes_unchecked(&buf)
+        }
+    };
+    if !bool::from(point.is_on_curve()) {
+        bail!("point not on curve");
+    }
+
+    Ok(point)
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProofStats {
+    pub proof_size: usize,
+    pub num_advice_columns: usize,
+    pub num_instance: Vec<usize>,
+    pub elapsed_ms: u64,
+}
+
+impl ProofStats {
+    pub fn new(pk: &ProvingKey<G1Affine>, proof: &Proof, elapsed: Duration) -> Self {
+        Self {
+            proof_size: proof.proof.len(),
+            num_advice_columns: pk.get_vk().cs().num_advice_columns(),
+            num_instance: proof.instances().iter().map(Vec::len).collect(),
+            elapsed_ms: elapsed.as_millis() as u64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProofMetadata {
+    pub git_version: String,
+    pub created_at: String,
+    // Only for EVM proof.
+    pub num_instance: Option<Vec<usize>>,
+}
+
+impl ProofMetadata {
+    pub fn from_proof(proof: &Proof) -> Self {
+        Self {
+            git_version: GIT_VERSION.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            num_instance: proof.num_instance.clone(),
+        }
+    }
+}
+
+/// JSON envelope of a proof for REST consumers. Instances are 0x-prefixed big-endian hex
+/// strings.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ProofEnvelope {
+    pub metadata: ProofMetadata,
+    #[serde(rename = "proof_b64", with = "base64")]
+    pub proof: Vec<u8>,
+    pub instances: Vec<Vec<String>>,
+}
+
+impl ProofEnvelope {
+    pub fn from_proof(proof: &Proof) -> Self {
+        let instances = proof
+            .instances()
+            .iter()
+            .map(|v| v.iter().map(fr_to_hex).collect())
+            .collect();
+
+        Self {
+            metadata: ProofMetadata::from_proof(proof),
+            proof: proof.proof.clone(),
+            instances,
+        }
+    }
+
+    /// Return the raw proof bytes and instances.
+    pub fn into_proof_and_instances(self) -> Result<(Vec<u8>, Vec<Vec<Fr>>)> {
+        let instances = self
+            .instances
+            .iter()
+            .map(|v| v.iter().map(|s| fr_from_hex(s)).collect())
+            .collect::<Result<_>>()?;
+
+        Ok((self.proof, instances))
+    }
+}
+
+pub fn write_proof_envelope(proof: &Proof, file_path: &str) -> Result<()> {
+    let fd = std::fs::File::create(file_path)?;
+    serde_json::to_writer_pretty(fd, &ProofEnvelope::from_proof(proof))?;
+
+    Ok(())
+}
+
+pub fn read_proof_envelope(file_path: &str) -> Result<(Vec<u8>, Vec<Vec<Fr>>)> {
+    let fd = std::fs::File::open(file_path)?;
+    let envelope: ProofEnvelope = serde_json::from_reader(fd)?;
+
+    envelope.into_proof_and_instances()
+}
+
+fn fr_to_hex(f: &Fr) -> String {
+    let mut bytes = f.to_bytes();
+    bytes.reverse();
+    format!("0x{}", hex::encode(bytes))
+}
+
+fn fr_from_hex(s: &str) -> Result<Fr> {
+    let mut bytes = <Fr as PrimeField>::Repr::default();
+    let decoded = hex::decode(s.trim_start_matches("0x"))?;
+    if decoded.len() != bytes.len() {
+        return Err(anyhow!("invalid instance length {} of {s}", decoded.len()));
+    }
+
+    bytes.copy_from_slice(&decoded);
+    bytes.reverse();
+    Option::from(Fr::from_repr(bytes)).ok_or_else(|| anyhow!("invalid instance {s}"))
 }
 
 fn dummy_protocol() -> Protocol<G1Affine> {
@@ -1,5 +1,8 @@
-use crate::utils::{get_block_trace_from_file, read_env_var};
+use crate::utils::{gen_rng, get_block_trace_from_file, read_env_var};
 use glob::glob;
+use halo2_proofs::{halo2curves::bn256::Bn256, poly::kzg::commitment::ParamsKZG};
+use once_cell::sync::Lazy;
+use std::path::Path;
 use types::eth::BlockTrace;
 
 pub mod aggregator;
@@ -7,6 +10,30 @@ pub mod mock_plonk;
 
 pub const PARAMS_DIR: &str = "./test_params";
 
+// Dir of the trace fixtures, default as `tests/traces` of this crate regardless of the CWD.
+static TRACE_FIXTURE_DIR: Lazy<String> = Lazy::new(|| {
+    read_env_var(
+        "TRACE_FIXTURE_DIR",
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/traces").to_string(),
+    )
+});
+
+// Max degree of params generated in memory, setup of higher degree is too slow for tests.
+const MAX_TEST_PARAMS_DEGREE: u32 = 20;
+
+/// Generate params in memory with a fixed RNG for tests, which makes it possible to run tests
+/// without downloaded params.
+///
+/// INSECURE: the toxic waste is reproducible, never use it outside tests.
+pub fn gen_insecure_test_params(degree: u32) -> ParamsKZG<Bn256> {
+    assert!(
+        degree <= MAX_TEST_PARAMS_DEGREE,
+        "test params degree {degree} must be no more than {MAX_TEST_PARAMS_DEGREE}"
+    );
+
+    ParamsKZG::<Bn256>::setup(degree, gen_rng())
+}
+
 pub fn parse_trace_path_from_mode(mode: &str) -> &'static str {
     let trace_path = match mode {
         "empty" => "./tests/traces/bridge/01.json",
@@ -24,6 +51,18 @@ pub fn parse_trace_path_from_mode(mode: &str) -> &'static str {
     trace_path
 }
 
+/// Load a trace fixture by its path relative to `TRACE_FIXTURE_DIR`, e.g. `erc20/1_transfer.json`.
+pub fn load_test_trace(name: &str) -> BlockTrace {
+    get_block_trace_from_file(Path::new(&*TRACE_FIXTURE_DIR).join(name))
+}
+
+/// Load the traces of a fixture dir relative to `TRACE_FIXTURE_DIR` as a chunk, e.g. `bridge`,
+/// sorted by block number.
+pub fn load_test_chunk(name: &str) -> Vec<BlockTrace> {
+    let dir = Path::new(&*TRACE_FIXTURE_DIR).join(name);
+    load_batch_traces(dir.to_str().unwrap()).1
+}
+
 pub fn load_block_traces_for_test() -> (Vec<String>, Vec<BlockTrace>) {
     let trace_path: String = read_env_var("TRACE_PATH", "".to_string());
     let paths: Vec<String> = if trace_path.is_empty() {
@@ -0,0 +1,175 @@
+use crate::{
+    utils::{output_dir, read_env_flag},
+    Proof,
+};
+use anyhow::Result;
+use halo2_proofs::{
+    arithmetic::CurveAffine,
+    halo2curves::{
+        bn256::{Bn256, G1Affine},
+        group::ff::PrimeField,
+    },
+    plonk::{verify_proof, VerifyingKey},
+    poly::{
+        kzg::{commitment::ParamsKZG, multiopen::VerifierSHPLONK, strategy::AccumulatorStrategy},
+        VerificationStrategy,
+    },
+    transcript::{EncodedChallenge, Transcript, TranscriptRead, TranscriptReadBuffer},
+};
+use itertools::Itertools;
+use once_cell::sync::Lazy;
+use serde_derive::{Deserialize, Serialize};
+use snark_verifier::{loader::native::NativeLoader, system::halo2::transcript::evm::EvmTranscript};
+use std::{fs, io, path::Path};
+
+// Dump the transcript of EVM proof to output dir by `DUMP_TRANSCRIPT=1`.
+pub static DUMP_TRANSCRIPT: Lazy<bool> = Lazy::new(|| read_env_flag("DUMP_TRANSCRIPT"));
+
+/// An operation on the Fiat-Shamir transcript. Field elements are 0x-prefixed big-endian hex.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TranscriptEvent {
+    ReadPoint { x: String, y: String },
+    ReadScalar { value: String },
+    CommonPoint { x: String, y: String },
+    CommonScalar { value: String },
+    SqueezeChallenge { value: String },
+}
+
+/// Transcript operations in order, replayed from a proof by the native verifier, to diff with the
+/// transcript reconstructed by the on-chain verifier.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct TranscriptDump {
+    pub events: Vec<TranscriptEvent>,
+    pub verified: bool,
+}
+
+impl TranscriptDump {
+    /// Write as `transcript.json` in the dir.
+    pub fn dump(&self, dir: &str) -> Result<()> {
+        let path = Path::new(dir).join("transcript.json");
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        log::info!("wrote transcript dump to {path:?}");
+
+        Ok(())
+    }
+}
+
+/// Replay the EVM proof (with Keccak transcript) and capture its transcript.
+pub fn dump_evm_transcript(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    proof: &Proof,
+) -> Result<TranscriptDump> {
+    let transcript: EvmTranscript<G1Affine, NativeLoader, _, _> =
+        TranscriptReadBuffer::<_, G1Affine, _>::init(proof.proof());
+    let mut transcript = RecordingTranscript::new(transcript);
+
+    let instances = proof.instances();
+    let instances = instances.iter().map(|ins| ins.as_slice()).collect_vec();
+    let verified = verify_proof::<_, VerifierSHPLONK<Bn256>, _, _, _>(
+        params,
+        vk,
+        AccumulatorStrategy::new(params),
+        &[instances.as_slice()],
+        &mut transcript,
+    )
+    .map(VerificationStrategy::<_, VerifierSHPLONK<Bn256>>::finalize);
+
+    Ok(TranscriptDump {
+        events: transcript.events,
+        verified: matches!(verified, Ok(true)),
+    })
+}
+
+/// Dump the transcript of EVM proof to the output dir if `DUMP_TRANSCRIPT=1`. Failures are only
+/// logged, since it's for debugging.
+pub(crate) fn try_dump_evm_transcript(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    proof: &Proof,
+) {
+    if !*DUMP_TRANSCRIPT {
+        return;
+    }
+
+    let dir = output_dir().unwrap_or(".");
+    if let Err(e) = dump_evm_transcript(params, vk, proof).and_then(|dump| dump.dump(dir)) {
+        log::error!("failed to dump transcript: {e}");
+    }
+}
+
+// Wrap a transcript to record the operations on it.
+struct RecordingTranscript<T> {
+    inner: T,
+    events: Vec<TranscriptEvent>,
+}
+
+impl<T> RecordingTranscript<T> {
+    fn new(inner: T) -> Self {
+        Self {
+            inner,
+            events: vec![],
+        }
+    }
+}
+
+fn field_to_hex<F: PrimeField>(f: &F) -> String {
+    let mut bytes = f.to_repr().as_ref().to_vec();
+    bytes.reverse();
+    format!("0x{}", hex::encode(bytes))
+}
+
+// Identity is encoded as (0, 0), same as EVM.
+fn point_to_hex<C: CurveAffine>(point: &C) -> (String, String) {
+    Option::from(point.coordinates())
+        .map(|c| (field_to_hex(c.x()), field_to_hex(c.y())))
+        .unwrap_or_else(|| {
+            let zero = field_to_hex(&C::Base::from(0));
+            (zero.clone(), zero)
+        })
+}
+
+impl<C: CurveAffine, E: EncodedChallenge<C>, T: Transcript<C, E>> Transcript<C, E>
+    for RecordingTranscript<T>
+{
+    fn squeeze_challenge(&mut self) -> E {
+        let challenge = self.inner.squeeze_challenge();
+        self.events.push(TranscriptEvent::SqueezeChallenge {
+            value: field_to_hex(&challenge.get_scalar()),
+        });
+        challenge
+    }
+
+    fn common_point(&mut self, point: C) -> io::Result<()> {
+        let (x, y) = point_to_hex(&point);
+        self.events.push(TranscriptEvent::CommonPoint { x, y });
+        self.inner.common_point(point)
+    }
+
+    fn common_scalar(&mut self, scalar: C::Scalar) -> io::Result<()> {
+        self.events.push(TranscriptEvent::CommonScalar {
+            value: field_to_hex(&scalar),
+        });
+        self.inner.common_scalar(scalar)
+    }
+}
+
+impl<C: CurveAffine, E: EncodedChallenge<C>, T: TranscriptRead<C, E>> TranscriptRead<C, E>
+    for RecordingTranscript<T>
+{
+    fn read_point(&mut self) -> io::Result<C> {
+        let point = self.inner.read_point()?;
+        let (x, y) = point_to_hex(&point);
+        self.events.push(TranscriptEvent::ReadPoint { x, y });
+        Ok(point)
+    }
+
+    fn read_scalar(&mut self) -> io::Result<C::Scalar> {
+        let scalar = self.inner.read_scalar()?;
+        self.events.push(TranscriptEvent::ReadScalar {
+            value: field_to_hex(&scalar),
+        });
+        Ok(scalar)
+    }
+}
@@ -1,10 +1,35 @@
-use crate::zkevm::circuit::{block_traces_to_witness_block, check_batch_capacity};
+use crate::{
+    config::{
+        AGG_LAYER1_DEGREE, AGG_LAYER2_DEGREE, AGG_LAYER3_DEGREE, AGG_LAYER4_DEGREE, CHUNK_DEGREE,
+        INNER_DEGREE, PROVING_TIME_BASE_MS, PROVING_TIME_PER_ROW_NS,
+    },
+    io::g1_point_len,
+    zkevm::circuit::{
+        batch_capacity_report, block_traces_to_witness_block,
+        block_traces_to_witness_block_with_opts, calculate_row_usage_of_trace,
+        calculate_row_usage_of_witness_block, capacity_profile, check_batch_capacity,
+        sanity_check_witness, CapacityPolicy, CapacityReport, WitnessError, WitnessOptions,
+        SUB_CIRCUIT_NAMES,
+    },
+};
+use aggregator::ChunkHash;
 use anyhow::{bail, Result};
+use blake2::{Blake2s256, Digest};
 use chrono::Utc;
+use eth_types::{H256, U256};
 use git_version::git_version;
 use halo2_proofs::{
-    halo2curves::bn256::{Bn256, Fr},
-    poly::kzg::commitment::ParamsKZG,
+    arithmetic::g_to_lagrange,
+    halo2curves::{
+        bn256::{Bn256, Fr, G1Affine, G2Affine, G2},
+        group::{Group, GroupEncoding},
+        pairing::Engine,
+        CurveAffine,
+    },
+    poly::{
+        commitment::{Params, ParamsProver},
+        kzg::commitment::ParamsKZG,
+    },
     SerdeFormat,
 };
 use log::LevelFilter;
@@ -12,24 +37,183 @@ use log4rs::{
     append::{
         console::{ConsoleAppender, Target},
         file::FileAppender,
+        Append,
     },
     config::{Appender, Config, Root},
+    encode::{pattern::PatternEncoder, writer::simple::SimpleWriter, Encode},
+    filter::threshold::ThresholdFilter,
 };
-use rand::{Rng, SeedableRng};
+use once_cell::sync::{Lazy, OnceCell};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
 use rand_xorshift::XorShiftRng;
+use serde::Deserialize;
+use serde_derive::Serialize;
+use serde_json::Value;
+use sha2::Sha256;
+use snark_verifier::util::arithmetic::PrimeField;
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
     fs::{self, metadata, File},
     io::{BufReader, Read},
     path::{Path, PathBuf},
     str::FromStr,
-    sync::Once,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{channel, sync_channel, Receiver, SyncSender, TrySendError},
+        Arc, Mutex, Once,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
-use types::eth::{BlockTrace, BlockTraceJsonRpcResult};
+use types::eth::{BlockTrace, BlockTraceJsonRpcResult, StorageTrace};
 use zkevm_circuits::evm_circuit::witness::Block;
 
 pub const DEFAULT_SERDE_FORMAT: SerdeFormat = SerdeFormat::RawBytesUnchecked;
+// Serde format set by env `SERDE_FORMAT`, e.g. `raw` to check curve points in staging.
+static SERDE_FORMAT: Lazy<SerdeFormat> = Lazy::new(|| {
+    let serde_fmt = match read_env_var("SERDE_FORMAT", String::new()).as_str() {
+        "processed" => SerdeFormat::Processed,
+        "raw" => SerdeFormat::RawBytes,
+        "raw_unchecked" => SerdeFormat::RawBytesUnchecked,
+        "" => DEFAULT_SERDE_FORMAT,
+        s => {
+            log::warn!("unknown SERDE_FORMAT {s}, use the default");
+            DEFAULT_SERDE_FORMAT
+        }
+    };
+    log::info!("resolved serde format: {:?}", serde_fmt);
+    serde_fmt
+});
 pub const GIT_VERSION: &str = git_version!();
 pub static LOGGER: Once = Once::new();
+// Output dir of the current run, set by `init_env_and_log`.
+static OUTPUT_DIR: OnceCell<String> = OnceCell::new();
+static LOG_PREFIX: OnceCell<String> = OnceCell::new();
+// Size limit of a trace in bytes, 0 means unlimited.
+static MAX_TRACE_BYTES: Lazy<u64> = Lazy::new(|| read_env_var("MAX_TRACE_BYTES", 0));
+// Start time of the process, for the timing summary on shutdown and the elapsed time of logs.
+static START_TIME: Lazy<Instant> = Lazy::new(Instant::now);
+// Max degree supported by the powers of tau ceremony.
+const MAX_PARAMS_DEGREE: u32 = 28;
+
+// Enable the sanity check of witness block by `WITNESS_SANITY=1`.
+static WITNESS_SANITY: Lazy<bool> = Lazy::new(|| read_env_flag("WITNESS_SANITY"));
+// Developer escape hatch to load params of experimental formats by `PARAMS_SKIP_LENGTH_CHECK=1`.
+// UNSAFE for production.
+static PARAMS_SKIP_LENGTH_CHECK: Lazy<bool> =
+    Lazy::new(|| read_env_flag("PARAMS_SKIP_LENGTH_CHECK"));
+// Keep the output dir of a failed or cancelled run by `KEEP_FAILED_OUTPUT=1`.
+static KEEP_FAILED_OUTPUT: Lazy<bool> = Lazy::new(|| read_env_flag("KEEP_FAILED_OUTPUT"));
+// Path of the pending `OutputDir`, cleaned up by the shutdown handler since `process::exit` skips
+// the drop.
+static PENDING_OUTPUT_DIR: Mutex<Option<String>> = Mutex::new(None);
+
+// Enter a `tracing` span of the proving phase, which is kept until the returned guard drops.
+// It's a no-op without feature `tracing-spans`.
+#[cfg(feature = "tracing-spans")]
+macro_rules! phase_span {
+    ($name:literal) => {
+        tracing::info_span!($name).entered()
+    };
+}
+#[cfg(not(feature = "tracing-spans"))]
+macro_rules! phase_span {
+    ($name:literal) => {
+        $crate::utils::NoopSpan
+    };
+}
+#[cfg(not(feature = "tracing-spans"))]
+pub(crate) struct NoopSpan;
+pub(crate) use phase_span;
+
+/// Source of the params bytes, e.g. local files, HTTP or an object store.
+pub trait ParamsSource {
+    /// Open a reader of the params of degree.
+    fn read(&self, degree: u32) -> Result<Box<dyn Read>>;
+
+    /// Length of the params of degree if known before reading, used to fail fast on invalid
+    /// params.
+    fn len_hint(&self, _degree: u32) -> Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+/// Load params from either a params dir (as `params{degree}`) or a params file.
+#[derive(Debug, Clone)]
+pub struct FileParamsSource {
+    params_dir: String,
+}
+
+impl FileParamsSource {
+    pub fn new(params_dir: &str) -> Self {
+        Self {
+            params_dir: params_dir.to_string(),
+        }
+    }
+
+    fn path(&self, degree: u32) -> Result<String> {
+        let params_path = if metadata(&self.params_dir)?.is_dir() {
+            // auto load
+            param_path_for_degree(&self.params_dir, degree)
+        } else {
+            self.params_dir.clone()
+        };
+        if !Path::new(&params_path).exists() {
+            bail!("Need to download params by `make download-setup -e degree={degree}`");
+        }
+
+        Ok(params_path)
+    }
+}
+
+impl ParamsSource for FileParamsSource {
+    fn read(&self, degree: u32) -> Result<Box<dyn Read>> {
+        Ok(Box::new(File::open(self.path(degree)?)?))
+    }
+
+    fn len_hint(&self, degree: u32) -> Result<Option<u64>> {
+        Ok(Some(metadata(self.path(degree)?)?.len()))
+    }
+}
+
+/// Load params from `{base_url}/params{degree}` by HTTP GET, requires feature `http-params`.
+#[derive(Debug, Clone)]
+pub struct HttpParamsSource {
+    base_url: String,
+}
+
+impl HttpParamsSource {
+    pub fn new(base_url: &str) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+        }
+    }
+}
+
+impl ParamsSource for HttpParamsSource {
+    #[cfg(feature = "http-params")]
+    fn read(&self, degree: u32) -> Result<Box<dyn Read>> {
+        let url = format!("{}/params{degree}", self.base_url);
+        log::info!("fetching params from {url}");
+        let resp = ureq::get(&url).call()?;
+
+        Ok(Box::new(resp.into_reader()))
+    }
+
+    #[cfg(not(feature = "http-params"))]
+    fn read(&self, _degree: u32) -> Result<Box<dyn Read>> {
+        bail!(
+            "cannot fetch params from {}, feature `http-params` is disabled",
+            self.base_url
+        )
+    }
+}
+
+/// Serde format of env `SERDE_FORMAT` (`processed`, `raw` or `raw_unchecked`), default as
+/// `DEFAULT_SERDE_FORMAT`.
+pub fn resolve_serde_format() -> SerdeFormat {
+    *SERDE_FORMAT
+}
 
 /// Load setup params from a file.
 pub fn load_params(
@@ -37,159 +221,2104 @@ pub fn load_params(
     degree: u32,
     serde_fmt: Option<SerdeFormat>,
 ) -> Result<ParamsKZG<Bn256>> {
-    log::info!("Start loading params with degree {}", degree);
-    let params_path = if metadata(params_dir)?.is_dir() {
-        // auto load
-        param_path_for_degree(params_dir, degree)
-    } else {
-        params_dir.to_string()
-    };
-    if !Path::new(&params_path).exists() {
-        bail!("Need to download params by `make download-setup -e degree={degree}`");
+    load_params_from_source(&FileParamsSource::new(params_dir), degree, serde_fmt)
+}
+
+/// Load setup params from the first of `params_dirs` which has the params file of the degree with
+/// the expected length, e.g. a local cache before a network mount.
+pub fn load_params_search(
+    params_dirs: &[PathBuf],
+    degree: u32,
+    serde_fmt: Option<SerdeFormat>,
+) -> Result<ParamsKZG<Bn256>> {
+    let expected_len = params_file_len(degree, serde_fmt.unwrap_or_else(resolve_serde_format));
+    for dir in params_dirs {
+        let dir = dir.to_string_lossy();
+        let path = param_path_for_degree(&dir, degree);
+        match metadata(&path) {
+            Ok(m) if m.len() == expected_len || *PARAMS_SKIP_LENGTH_CHECK => {
+                log::info!("params of degree {degree} found in {dir}");
+                return load_params(&dir, degree, serde_fmt);
+            }
+            Ok(m) => log::warn!(
+                "skip params {path} of invalid len {}, expected {expected_len}",
+                m.len()
+            ),
+            Err(_) => log::debug!("params {path} not found"),
+        }
     }
-    let f = File::open(params_path)?;
 
-    // check params file length:
-    //   len: 4 bytes
-    //   g: 2**DEGREE g1 points, each 32 bytes(256bits)
-    //   g_lagrange: 2**DEGREE g1 points, each 32 bytes(256bits)
-    //   g2: g2 point, 64 bytes
-    //   s_g2: g2 point, 64 bytes
-    let file_size = f.metadata()?.len();
-    let g1_num = 2 * (1 << degree);
-    let g2_num = 2;
+    bail!("params of degree {degree} not found in any of {params_dirs:?}")
+}
 
-    let serde_fmt = serde_fmt.unwrap_or(DEFAULT_SERDE_FORMAT);
-    let g1_bytes_len = match serde_fmt {
-        SerdeFormat::Processed => 32,
-        SerdeFormat::RawBytes | SerdeFormat::RawBytesUnchecked => 64,
+/// Load setup params from a custom source, with the same validation as `load_params`.
+pub fn load_params_from_source(
+    source: &dyn ParamsSource,
+    degree: u32,
+    serde_fmt: Option<SerdeFormat>,
+) -> Result<ParamsKZG<Bn256>> {
+    let _span = phase_span!("load_params");
+    log::info!("Start loading params with degree {}", degree);
+    let serde_fmt = serde_fmt.unwrap_or_else(resolve_serde_format);
+    let expected_len = params_file_len(degree, serde_fmt);
+    let check_len = |len: u64| -> Result<()> {
+        if len != expected_len {
+            if !*PARAMS_SKIP_LENGTH_CHECK {
+                if let Some(file_degree) =
+                    (1..degree).find(|d| params_file_len(*d, serde_fmt) == len)
+                {
+                    bail!("requested degree {degree} but file is degree {file_degree}; params cannot be upsized, need a larger SRS by `make download-setup -e degree={degree}`");
+                }
+                return Err(anyhow::format_err!("invalid params file len {} for degree {}. check DEGREE or remove the invalid params file", len, degree));
+            }
+            log::warn!(
+                "PARAMS_SKIP_LENGTH_CHECK is set, ignore invalid params file len {} for degree {}. NEVER use it in production",
+                len,
+                degree
+            );
+        }
+        Ok(())
     };
-    let g2_bytes_len = 2 * g1_bytes_len;
-    let expected_len = 4 + g1_num * g1_bytes_len + g2_num * g2_bytes_len;
-    if file_size != expected_len {
-        return Err(anyhow::format_err!("invalid params file len {} for degree {}. check DEGREE or remove the invalid params file", file_size, degree));
+
+    let len_hint = source.len_hint(degree)?;
+    if let Some(len) = len_hint {
+        check_len(len)?;
     }
 
-    let p = ParamsKZG::<Bn256>::read_custom::<_>(&mut BufReader::new(f), serde_fmt)?;
+    let mut reader = CountingReader::new(BufReader::new(source.read(degree)?), expected_len);
+    let p = ParamsKZG::<Bn256>::read_custom::<_>(&mut reader, serde_fmt).map_err(|e| {
+        anyhow::format_err!(
+            "failed to read params for degree {}: {} (read {} of {} expected bytes)",
+            degree,
+            e,
+            reader.bytes_read(),
+            expected_len
+        )
+    })?;
+
+    // Only check the length after reading if it's unknown before.
+    if len_hint.is_none() {
+        std::io::copy(&mut reader, &mut std::io::sink())?;
+        check_len(reader.bytes_read())?;
+    }
     log::info!("load params successfully!");
     Ok(p)
 }
 
+/// Check the params match a known-good `s_g2` of the trusted setup ceremony, and the g1 powers
+/// are generated by the same secret.
+///
+/// No ceremony commitment is shipped, the caller supplies the published `s_g2`, e.g. parsed by
+/// `g2_from_hex`. Notice that `s_g2` doesn't depend on the degree, since downsizing keeps it as is.
+pub fn verify_params_commitment(params: &ParamsKZG<Bn256>, expected_s_g2: G2Affine) -> Result<()> {
+    if params.s_g2() != expected_s_g2 {
+        bail!(
+            "params s_g2 {:?} mismatches the expected {:?}",
+            params.s_g2(),
+            expected_s_g2
+        );
+    }
+
+    // e(g, s_g2) = e(s_g, g2)
+    let g = params.get_g();
+    if g.len() < 2 {
+        bail!("params must have at least 2 g1 points");
+    }
+    if Bn256::pairing(&g[0], &params.s_g2()) != Bn256::pairing(&g[1], &params.g2()) {
+        bail!("params g1 powers are inconsistent with s_g2");
+    }
+
+    Ok(())
+}
+
+/// Compute `g_lagrange` of the domain of degree from the first `2^degree` points of `g`, the same
+/// as `ParamsKZG::setup` and `downsize`.
+pub fn compute_g_lagrange(g: &[G1Affine], degree: u32) -> Vec<G1Affine> {
+    let n = 1 << degree;
+    assert!(
+        g.len() >= n,
+        "need {n} g1 points for degree {degree}, but got {}",
+        g.len()
+    );
+
+    g_to_lagrange(g[..n].iter().map(|g| g.to_curve()).collect(), degree)
+}
+
+/// Smoke test of params integrity, which checks `sample` randomly chosen g1 points are on curve,
+/// and `g2` and `s_g2` are on curve and in the subgroup. It's probabilistic and much faster than
+/// a full validation, but could only catch gross corruption.
+pub fn quick_verify_params(params: &ParamsKZG<Bn256>, sample: usize) -> Result<()> {
+    let g = params.get_g();
+    let mut rng = rand::thread_rng();
+    for idx in rand::seq::index::sample(&mut rng, g.len(), sample.min(g.len())) {
+        // Cofactor of BN254 G1 is 1, so on curve means in subgroup.
+        if !bool::from(g[idx].is_on_curve()) {
+            bail!("params g[{idx}] is not on curve");
+        }
+    }
+
+    for (name, point) in [("g2", params.g2()), ("s_g2", params.s_g2())] {
+        if !bool::from(point.is_on_curve()) {
+            bail!("params {name} is not on curve");
+        }
+
+        // (r - 1) * P + P = O iff P is in the subgroup of order r.
+        let point = G2::from(point);
+        if !bool::from((point * -Fr::one() + point).is_identity()) {
+            bail!("params {name} is not in subgroup");
+        }
+    }
+
+    log::debug!("quick verified params of degree {}", params.k());
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct ParamsVerifyOptions {
+    pub serde_fmt: Option<SerdeFormat>,
+    /// Hex encoded sha256 of the params file, skip the checksum if None.
+    pub sha256: Option<String>,
+    /// The published `s_g2` of the setup, default as the `s_g2` of params.
+    pub expected_s_g2: Option<G2Affine>,
+    /// Number of g1 points sampled to check on curve.
+    pub sample: usize,
+}
+
+impl Default for ParamsVerifyOptions {
+    fn default() -> Self {
+        Self {
+            serde_fmt: None,
+            sha256: None,
+            expected_s_g2: None,
+            sample: 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum ParamsCheckResult {
+    Passed,
+    Failed(String),
+    Skipped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParamsVerifyReport {
+    pub path: String,
+    pub degree: u32,
+    /// (name, result) of each check in order.
+    pub checks: Vec<(&'static str, ParamsCheckResult)>,
+}
+
+impl ParamsVerifyReport {
+    pub fn is_ok(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|(_, result)| matches!(result, ParamsCheckResult::Failed(_)))
+    }
+}
+
+/// Fully verify the params file of degree: file length, checksum (if set), degree consistency,
+/// g1 and g2 points (on curve and in subgroup), and the pairing consistency with `s_g2`. Checks
+/// after a failed length or load are skipped. Return error only if the file cannot be read.
+pub fn verify_params_full(
+    params_dir: &str,
+    degree: u32,
+    opts: &ParamsVerifyOptions,
+) -> Result<ParamsVerifyReport> {
+    let path = param_path_for_degree(params_dir, degree);
+    let serde_fmt = opts.serde_fmt.unwrap_or_else(resolve_serde_format);
+    let mut report = ParamsVerifyReport {
+        path: path.clone(),
+        degree,
+        checks: vec![],
+    };
+    let result = |r: Result<()>| match r {
+        Ok(()) => ParamsCheckResult::Passed,
+        Err(e) => ParamsCheckResult::Failed(e.to_string()),
+    };
+
+    let len = metadata(&path)?.len();
+    let expected_len = params_file_len(degree, serde_fmt);
+    let length = if len == expected_len {
+        ParamsCheckResult::Passed
+    } else {
+        ParamsCheckResult::Failed(format!("file len {len}, expected {expected_len}"))
+    };
+
+    let checksum = match &opts.sha256 {
+        Some(expected) => {
+            let actual = file_sha256(Path::new(&path))?;
+            if actual.eq_ignore_ascii_case(expected.trim_start_matches("0x")) {
+                ParamsCheckResult::Passed
+            } else {
+                ParamsCheckResult::Failed(format!("sha256 {actual}, expected {expected}"))
+            }
+        }
+        None => ParamsCheckResult::Skipped,
+    };
+
+    let params = if length == ParamsCheckResult::Passed {
+        load_params(params_dir, degree, Some(serde_fmt))
+    } else {
+        Err(anyhow::format_err!("skipped by invalid length"))
+    };
+    report.checks.push(("length", length));
+    report.checks.push(("checksum", checksum));
+
+    match params {
+        Ok(params) => {
+            report.checks.push(("load", ParamsCheckResult::Passed));
+
+            let degree_check = if params.k() != degree {
+                Err(anyhow::format_err!("params of degree {}", params.k()))
+            } else if params.get_g().len() != 1 << degree {
+                Err(anyhow::format_err!(
+                    "params of {} g1 points",
+                    params.get_g().len()
+                ))
+            } else {
+                Ok(())
+            };
+            report.checks.push(("degree", result(degree_check)));
+            report
+                .checks
+                .push(("points", result(quick_verify_params(&params, opts.sample))));
+            let expected_s_g2 = opts.expected_s_g2.unwrap_or_else(|| params.s_g2());
+            report.checks.push((
+                "commitment",
+                result(verify_params_commitment(&params, expected_s_g2)),
+            ));
+        }
+        Err(e) => {
+            report
+                .checks
+                .push(("load", ParamsCheckResult::Failed(e.to_string())));
+            for name in ["degree", "points", "commitment"] {
+                report.checks.push((name, ParamsCheckResult::Skipped));
+            }
+        }
+    }
+
+    log::info!("verified params {path}: {:?}", report.checks);
+    Ok(report)
+}
+
+/// Parse a G2 point from its hex encoded compressed bytes, e.g. the published `s_g2` of a
+/// trusted setup ceremony.
+pub fn g2_from_hex(s: &str) -> Result<G2Affine> {
+    let mut repr = <G2Affine as GroupEncoding>::Repr::default();
+    let bytes = hex::decode(s.trim_start_matches("0x"))?;
+    if bytes.len() != repr.as_ref().len() {
+        bail!("invalid G2 point length {}", bytes.len());
+    }
+
+    repr.as_mut().copy_from_slice(&bytes);
+    Option::from(G2Affine::from_bytes(&repr)).ok_or_else(|| anyhow::format_err!("invalid G2 point"))
+}
+
+// Wraps a reader and counts the consumed bytes, so a failed read could report the offset where
+// the input stopped (e.g. a partially downloaded params file). It also logs the progress of every
+// `PROGRESS_LOG_STEP` bytes.
+struct CountingReader<R> {
+    inner: R,
+    bytes_read: u64,
+    total: u64,
+}
+
+const PROGRESS_LOG_STEP: u64 = 256 * 1024 * 1024;
+
+impl<R: Read> CountingReader<R> {
+    fn new(inner: R, total: u64) -> Self {
+        Self {
+            inner,
+            bytes_read: 0,
+            total,
+        }
+    }
+
+    fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        let prev = self.bytes_read;
+        self.bytes_read += n as u64;
+
+        if self.bytes_read / PROGRESS_LOG_STEP > prev / PROGRESS_LOG_STEP {
+            log::debug!(
+                "loading params: {}MB of {}MB",
+                self.bytes_read / 1024 / 1024,
+                self.total / 1024 / 1024
+            );
+        }
+
+        Ok(n)
+    }
+}
+
+/// Size of a block trace file, without parsing it.
+pub fn block_trace_file_size<P: AsRef<Path>>(path: P) -> std::io::Result<u64> {
+    Ok(metadata(path)?.len())
+}
+
+/// Error of reading an input larger than the limit.
+#[derive(Debug)]
+pub struct TooLarge {
+    pub max_bytes: u64,
+}
+
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "input exceeds the limit of {} bytes", self.max_bytes)
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+// Read to the end of buffer, and return `TooLarge` once more than `max_bytes` (0 means unlimited)
+// are read, so that the oversized input is never fully buffered.
+fn read_to_end_limited<R: Read>(reader: R, max_bytes: u64, buffer: &mut Vec<u8>) -> Result<()> {
+    if max_bytes == 0 {
+        let mut reader = reader;
+        reader.read_to_end(buffer)?;
+        return Ok(());
+    }
+
+    reader.take(max_bytes + 1).read_to_end(buffer)?;
+    if buffer.len() as u64 > max_bytes {
+        buffer.clear();
+        return Err(TooLarge { max_bytes }.into());
+    }
+
+    Ok(())
+}
+
 /// get a block-result from file
 pub fn get_block_trace_from_file<P: AsRef<Path>>(path: P) -> BlockTrace {
     let mut buffer = Vec::new();
-    let mut f = File::open(&path).unwrap();
-    f.read_to_end(&mut buffer).unwrap();
+    let f = File::open(&path).unwrap();
+    read_to_end_limited(f, *MAX_TRACE_BYTES, &mut buffer)
+        .unwrap_or_else(|e| panic!("unable to read {:?}: {}", path.as_ref(), e));
 
     serde_json::from_slice::<BlockTrace>(&buffer).unwrap_or_else(|e1| {
         serde_json::from_slice::<BlockTraceJsonRpcResult>(&buffer)
-            .map_err(|e2| {
-                panic!(
-                    "unable to load BlockTrace from {:?}, {:?}, {:?}",
-                    path.as_ref(),
-                    e1,
-                    e2
-                )
+            .map(|r| r.result)
+            .or_else(|e2| {
+                serde_json::from_slice::<Value>(&buffer)
+                    .map_err(anyhow::Error::from)
+                    .and_then(normalize_block_trace)
+                    .map_err(|e3| {
+                        panic!(
+                            "unable to load BlockTrace from {:?}, {:?}, {:?}, {:?}",
+                            path.as_ref(),
+                            e1,
+                            e2,
+                            e3
+                        )
+                    })
             })
             .unwrap()
-            .result
     })
 }
 
-pub fn read_env_var<T: Clone + FromStr>(var_name: &'static str, default: T) -> T {
-    std::env::var(var_name)
-        .map(|s| s.parse::<T>().unwrap_or_else(|_| default.clone()))
-        .unwrap_or(default)
+/// Load a block trace from reader, return `TooLarge` error if it's more than `max_bytes`.
+/// Default as env `MAX_TRACE_BYTES` if `max_bytes` is None.
+pub fn block_trace_from_reader<R: Read>(reader: R, max_bytes: Option<u64>) -> Result<BlockTrace> {
+    let mut loader = BlockTraceLoader::new();
+    if let Some(max_bytes) = max_bytes {
+        loader = loader.with_max_bytes(max_bytes);
+    }
+
+    loader.load_from_reader(reader, "reader")
 }
 
+/// Load block traces with a reusable buffer, to avoid allocating on each load in a tight loop.
 #[derive(Debug)]
-pub struct BatchMetric {
-    pub num_block: usize,
-    pub num_tx: usize,
-    pub num_step: usize,
+pub struct BlockTraceLoader {
+    buffer: Vec<u8>,
+    max_bytes: u64,
 }
 
-pub fn metric_of_witness_block(block: &Block<Fr>) -> BatchMetric {
-    BatchMetric {
-        num_block: block.context.ctxs.len(),
-        num_tx: block.txs.len(),
-        num_step: block.txs.iter().map(|tx| tx.steps.len()).sum::<usize>(),
+impl Default for BlockTraceLoader {
+    fn default() -> Self {
+        Self {
+            buffer: Vec::new(),
+            max_bytes: *MAX_TRACE_BYTES,
+        }
     }
 }
 
-pub fn chunk_trace_to_witness_block(mut chunk_trace: Vec<BlockTrace>) -> Result<Block<Fr>> {
-    if chunk_trace.is_empty() {
-        bail!("Empty chunk trace");
+impl BlockTraceLoader {
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    // Check if the trace exceeds the circuit capacity.
-    check_batch_capacity(&mut chunk_trace)?;
+    /// Override the size limit of env `MAX_TRACE_BYTES`, 0 means unlimited.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes;
+        self
+    }
+
+    /// Same as `get_block_trace_from_file`, but return error instead of panic.
+    pub fn load<P: AsRef<Path>>(&mut self, path: P) -> Result<BlockTrace> {
+        let f = File::open(&path)?;
+        self.load_from_reader(f, path.as_ref())
+    }
+
+    pub fn load_from_reader<R: Read, D: std::fmt::Debug>(
+        &mut self,
+        reader: R,
+        source: D,
+    ) -> Result<BlockTrace> {
+        self.buffer.clear();
+        read_to_end_limited(reader, self.max_bytes, &mut self.buffer)?;
 
-    block_traces_to_witness_block(&chunk_trace)
+        serde_json::from_slice::<BlockTrace>(&self.buffer).or_else(|e1| {
+            serde_json::from_slice::<BlockTraceJsonRpcResult>(&self.buffer)
+                .map(|r| r.result)
+                .or_else(|e2| {
+                    serde_json::from_slice::<Value>(&self.buffer)
+                        .map_err(anyhow::Error::from)
+                        .and_then(normalize_block_trace)
+                        .map_err(|e3| {
+                            anyhow::format_err!(
+                                "unable to load BlockTrace from {:?}, {:?}, {:?}, {:?}",
+                                source,
+                                e1,
+                                e2,
+                                e3
+                            )
+                        })
+                })
+        })
+    }
 }
 
-// Return the output dir.
-pub fn init_env_and_log(id: &str) -> String {
-    dotenv::dotenv().ok();
-    let output_dir = create_output_dir(id);
+/// Load the block traces on a pool of threads, and yield `(index of path, result)` as each one is
+/// parsed, so a slow file doesn't block the ready ones. The index could be used to restore the
+/// input order.
+pub fn get_block_traces_parallel_unordered(
+    paths: Vec<PathBuf>,
+) -> impl Iterator<Item = (usize, Result<BlockTrace>)> {
+    let num_workers = thread::available_parallelism()
+        .map(usize::from)
+        .unwrap_or(1)
+        .min(paths.len());
+    let paths = Arc::new(paths);
+    let next = Arc::new(AtomicUsize::new(0));
+    let (sender, receiver) = channel();
 
-    LOGGER.call_once(|| {
-        // TODO: cannot support complicated `RUST_LOG` for now.
-        let log_level = read_env_var("RUST_LOG", "INFO".to_string());
-        let log_level = LevelFilter::from_str(&log_level).unwrap_or(LevelFilter::Info);
+    for _ in 0..num_workers {
+        let (paths, next, sender) = (paths.clone(), next.clone(), sender.clone());
+        thread::spawn(move || {
+            let mut loader = BlockTraceLoader::new();
+            loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= paths.len() {
+                    break;
+                }
+                // Stop loading if the iterator is dropped.
+                if sender.send((index, loader.load(&paths[index]))).is_err() {
+                    break;
+                }
+            }
+        });
+    }
 
-        let mut log_file_path = PathBuf::from(output_dir.clone());
-        log_file_path.push("log.txt");
-        let log_file = FileAppender::builder().build(log_file_path).unwrap();
+    // Ends once all workers are done and their senders are dropped.
+    receiver.into_iter()
+}
+
+/// Load the traces of chunks on a background thread, so loading the next chunks overlaps with
+/// proving the current one. At most `bound` loaded chunks are queued, then loading blocks until the
+/// queue is drained.
+#[derive(Debug)]
+pub struct TraceStream {
+    receiver: Option<Receiver<Result<Vec<BlockTrace>>>>,
+    handle: Option<JoinHandle<()>>,
+}
 
-        let stderr = ConsoleAppender::builder().target(Target::Stderr).build();
+impl TraceStream {
+    pub fn new(chunk_paths: Vec<Vec<PathBuf>>, bound: usize) -> Self {
+        let (sender, receiver) = sync_channel(bound);
+        let handle = thread::spawn(move || {
+            let mut loader = BlockTraceLoader::new();
+            for paths in chunk_paths {
+                let chunk = paths.iter().map(|path| loader.load(path)).collect();
+                // Stop loading if the stream is dropped.
+                if sender.send(chunk).is_err() {
+                    break;
+                }
+            }
+        });
 
-        let config = Config::builder()
-            .appenders([
-                Appender::builder().build("log-file", Box::new(log_file)),
-                Appender::builder().build("stderr", Box::new(stderr)),
-            ])
-            .build(
-                Root::builder()
-                    .appender("log-file")
-                    .appender("stderr")
-                    .build(log_level),
-            )
-            .unwrap();
+        Self {
+            receiver: Some(receiver),
+            handle: Some(handle),
+        }
+    }
+}
 
-        log4rs::init_config(config).unwrap();
+impl Iterator for TraceStream {
+    type Item = Result<Vec<BlockTrace>>;
 
-        log::info!("git version {}", GIT_VERSION);
-    });
+    fn next(&mut self) -> Option<Self::Item> {
+        self.receiver.as_ref()?.recv().ok()
+    }
+}
 
-    output_dir
+impl Drop for TraceStream {
+    fn drop(&mut self) {
+        // Drop the receiver first to unblock the loading thread.
+        self.receiver.take();
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::error!("trace loading thread panicked");
+            }
+        }
+    }
 }
 
-fn create_output_dir(id: &str) -> String {
-    let mode = read_env_var("MODE", "multi".to_string());
-    let output = read_env_var(
-        "OUTPUT_DIR",
-        format!(
-            "{}_output_{}_{}",
-            id,
-            mode,
-            Utc::now().format("%Y%m%d_%H%M%S")
-        ),
-    );
+/// Returned by `TraceProducer::try_push` with the rejected chunk, if the queue is full.
+#[derive(Debug)]
+pub struct Busy(pub Vec<BlockTrace>);
 
-    let output_dir = PathBuf::from_str(&output).unwrap();
-    fs::create_dir_all(output_dir).unwrap();
+impl std::fmt::Display for Busy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "trace queue is full")
+    }
+}
+
+impl std::error::Error for Busy {}
+
+/// Bounded queue of chunk traces between trace sources and proving, so a fast source is blocked
+/// (or gets `Busy`) rather than piling traces up in memory.
+#[derive(Debug)]
+pub struct TraceQueue {
+    receiver: Receiver<Vec<BlockTrace>>,
+    depth: Arc<AtomicUsize>,
+    capacity: usize,
+}
 
-    output
+/// Producer side of a `TraceQueue`, which could be cloned to other threads.
+#[derive(Debug, Clone)]
+pub struct TraceProducer {
+    sender: SyncSender<Vec<BlockTrace>>,
+    depth: Arc<AtomicUsize>,
 }
 
-pub fn param_path_for_degree(params_dir: &str, degree: u32) -> String {
-    format!("{params_dir}/params{degree}")
+impl TraceQueue {
+    /// Return the queue and its producer. The queue ends once all producers are dropped.
+    pub fn new(capacity: usize) -> (TraceProducer, Self) {
+        let (sender, receiver) = sync_channel(capacity);
+        let depth = Arc::new(AtomicUsize::new(0));
+        let producer = TraceProducer {
+            sender,
+            depth: depth.clone(),
+        };
+
+        (
+            producer,
+            Self {
+                receiver,
+                depth,
+                capacity,
+            },
+        )
+    }
+
+    /// Number of queued chunks.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Take the next chunk, blocking until one is pushed. Return None if all producers are dropped.
+    pub fn pop(&self) -> Option<Vec<BlockTrace>> {
+        let chunk = self.receiver.recv().ok()?;
+        let depth = self.depth.fetch_sub(1, Ordering::Relaxed) - 1;
+        log::debug!("trace queue depth {depth}/{}", self.capacity);
+
+        Some(chunk)
+    }
+}
+
+impl Iterator for TraceQueue {
+    type Item = Vec<BlockTrace>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.pop()
+    }
+}
+
+impl TraceProducer {
+    /// Push a chunk, blocking while the queue is full. Return error if the queue is dropped.
+    pub fn push(&self, chunk: Vec<BlockTrace>) -> Result<()> {
+        // Count before sending, so the consumer never sees a negative depth.
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        if self.sender.send(chunk).is_err() {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+            bail!("trace queue is dropped");
+        }
+
+        Ok(())
+    }
+
+    /// Push a chunk without blocking, and return `Busy` if the queue is full.
+    pub fn try_push(&self, chunk: Vec<BlockTrace>) -> Result<()> {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        match self.sender.try_send(chunk) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(chunk)) => {
+                self.depth.fetch_sub(1, Ordering::Relaxed);
+                Err(Busy(chunk).into())
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.depth.fetch_sub(1, Ordering::Relaxed);
+                bail!("trace queue is dropped")
+            }
+        }
+    }
+
+    /// Push all chunks of a loader, e.g. `TraceStream`, until the first loading error.
+    pub fn push_all(&self, chunks: impl Iterator<Item = Result<Vec<BlockTrace>>>) -> Result<()> {
+        for chunk in chunks {
+            self.push(chunk?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Number of queued chunks.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+}
+
+/// Isolate a tx of the block trace, with the per-tx storage trace as the storage trace of block.
+/// Return error if the tx isn't found, or its state accesses aren't covered by the per-tx storage
+/// trace.
+pub fn filter_block_trace(block_trace: &BlockTrace, tx_hash: H256) -> Result<BlockTrace> {
+    let tx_idx = block_trace
+        .transactions
+        .iter()
+        .position(|tx| tx.tx_hash == tx_hash)
+        .ok_or_else(|| {
+            anyhow::format_err!(
+                "tx {tx_hash:?} not found in block {:?}",
+                block_trace.header.number
+            )
+        })?;
+    let storage_trace = block_trace.tx_storage_trace.get(tx_idx).ok_or_else(|| {
+        anyhow::format_err!(
+            "block {:?} has no storage trace of tx {tx_idx}, cannot isolate tx {tx_hash:?}",
+            block_trace.header.number
+        )
+    })?;
+    let tx = &block_trace.transactions[tx_idx];
+    let result = block_trace.execution_results.get(tx_idx).ok_or_else(|| {
+        anyhow::format_err!(
+            "block {:?} has no execution result of tx {tx_idx}, cannot isolate tx {tx_hash:?}",
+            block_trace.header.number
+        )
+    })?;
+
+    // All accounts accessed by the tx must have proofs in its storage trace.
+    let proofs = storage_trace.proofs.as_ref();
+    let accessed = [Some(tx.from), tx.to, block_trace.coinbase.address]
+        .into_iter()
+        .chain(
+            result
+                .from
+                .iter()
+                .chain(result.to.iter())
+                .chain(result.account_after.iter())
+                .chain(result.account_created.iter())
+                .chain(
+                    result
+                        .exec_steps
+                        .iter()
+                        .filter_map(|step| step.extra_data.as_ref())
+                        .filter_map(|extra| extra.proof_list.as_ref())
+                        .flatten(),
+                )
+                .map(|account| account.address),
+        )
+        .flatten();
+    for address in accessed {
+        if !proofs.map_or(false, |proofs| proofs.contains_key(&address)) {
+            bail!("tx {tx_hash:?} is not self-contained: no proof of account {address:?}");
+        }
+    }
+
+    Ok(BlockTrace {
+        transactions: vec![tx.clone()],
+        execution_results: vec![result.clone()],
+        storage_trace: storage_trace.clone(),
+        tx_storage_trace: vec![storage_trace.clone()],
+        ..block_trace.clone()
+    })
+}
+
+/// Keep the first `n_txs` txs of the block trace, e.g. to bisect the tx failing a block by proving
+/// the prefixes. The proofs of the block storage trace, against the block root before, are pruned
+/// to the accounts and slots of the kept per-tx storage traces (and the coinbase), and the root
+/// after is of the last kept tx. Deletion proofs are all kept, since the extra nodes are harmless.
+/// The header txs and `gas_used` are truncated too, but `transactions_root` isn't recomputed, as
+/// it's not constrained by the circuits.
+pub fn truncate_block_trace(block_trace: &BlockTrace, n_txs: usize) -> Result<BlockTrace> {
+    let num_txs = block_trace.transactions.len();
+    if n_txs > num_txs {
+        bail!(
+            "cannot truncate block {:?} of {num_txs} txs to {n_txs} txs",
+            block_trace.header.number
+        );
+    }
+    if n_txs == num_txs {
+        return Ok(block_trace.clone());
+    }
+    if block_trace.tx_storage_trace.len() != num_txs {
+        bail!(
+            "block {:?} has {} per-tx storage traces of {num_txs} txs, cannot truncate",
+            block_trace.header.number,
+            block_trace.tx_storage_trace.len()
+        );
+    }
+
+    let tx_storage_trace = &block_trace.tx_storage_trace[..n_txs];
+    let mut root = block_trace.storage_trace.root_before;
+    for (tx_idx, storage_trace) in tx_storage_trace.iter().enumerate() {
+        if storage_trace.root_before != root {
+            bail!(
+                "storage trace of tx {tx_idx} starts from {:?}, but previous root is {root:?}",
+                storage_trace.root_before
+            );
+        }
+        root = storage_trace.root_after;
+    }
+
+    let accounts: HashSet<_> = tx_storage_trace
+        .iter()
+        .flat_map(|trace| trace.proofs.iter().flat_map(|proofs| proofs.keys()))
+        .chain(block_trace.coinbase.address.iter())
+        .collect();
+    let slots: HashSet<_> = tx_storage_trace
+        .iter()
+        .flat_map(|trace| {
+            trace
+                .storage_proofs
+                .iter()
+                .flat_map(|(address, proofs)| proofs.keys().map(move |key| (address, key)))
+        })
+        .collect();
+
+    let storage_trace = &block_trace.storage_trace;
+    let proofs = storage_trace.proofs.as_ref().map(|proofs| {
+        proofs
+            .iter()
+            .filter(|(address, _)| accounts.contains(address))
+            .map(|(address, proof)| (*address, proof.clone()))
+            .collect()
+    });
+    let storage_proofs = storage_trace
+        .storage_proofs
+        .iter()
+        .map(|(address, proofs)| {
+            let proofs = proofs
+                .iter()
+                .filter(|(key, _)| slots.contains(&(address, *key)))
+                .map(|(key, proof)| (*key, proof.clone()))
+                .collect::<HashMap<_, _>>();
+            (*address, proofs)
+        })
+        .filter(|(_, proofs)| !proofs.is_empty())
+        .collect();
+
+    let execution_results = block_trace.execution_results[..n_txs].to_vec();
+    let mut header = block_trace.header.clone();
+    header.transactions.truncate(n_txs);
+    header.gas_used = execution_results.iter().map(|r| r.gas).sum::<u64>().into();
+
+    Ok(BlockTrace {
+        header,
+        transactions: block_trace.transactions[..n_txs].to_vec(),
+        execution_results,
+        storage_trace: StorageTrace {
+            root_before: storage_trace.root_before,
+            root_after: root,
+            proofs,
+            storage_proofs,
+            deletion_proofs: storage_trace.deletion_proofs.clone(),
+        },
+        tx_storage_trace: tx_storage_trace.to_vec(),
+        ..block_trace.clone()
+    })
+}
+
+/// Decode `BlockTrace` from a parsed JSON value, with the same fallbacks as
+/// `get_block_trace_from_file`: plain trace, JSON-RPC result, then normalized trace.
+pub fn block_trace_from_value(value: Value) -> Result<BlockTrace> {
+    // Deserialize from a reference to avoid cloning the value for each attempt.
+    let e1 = match BlockTrace::deserialize(&value) {
+        Ok(trace) => return Ok(trace),
+        Err(e) => e,
+    };
+    let e2 = match BlockTraceJsonRpcResult::deserialize(&value) {
+        Ok(r) => return Ok(r.result),
+        Err(e) => e,
+    };
+
+    normalize_block_trace(value)
+        .map_err(|e3| anyhow::format_err!("unable to decode BlockTrace, {e1:?}, {e2:?}, {e3:?}"))
+}
+
+/// Deterministic encoding of a chunk, for content-addressed caching and fingerprinting.
+/// Since `BlockTrace` has `HashMap` fields, the traces are encoded as canonical JSON with sorted
+/// keys (as `serde_json::Map` without `preserve_order`). It's not compact, but stable across runs
+/// and platforms.
+pub fn serialize_chunk(block_traces: &[BlockTrace]) -> Vec<u8> {
+    let value = serde_json::to_value(block_traces).expect("BlockTrace must be serializable");
+    serde_json::to_vec(&value).unwrap()
+}
+
+pub fn deserialize_chunk(buf: &[u8]) -> Result<Vec<BlockTrace>> {
+    Ok(serde_json::from_slice(buf)?)
+}
+
+// Canonical field names of `BlockTrace`, `StorageTrace` and `ExecutionResult`.
+const BLOCK_TRACE_FIELDS: [&str; 7] = [
+    "chainID",
+    "coinbase",
+    "header",
+    "transactions",
+    "executionResults",
+    "storageTrace",
+    "txStorageTraces",
+];
+const STORAGE_TRACE_FIELDS: [&str; 5] = [
+    "rootBefore",
+    "rootAfter",
+    "proofs",
+    "storageProofs",
+    "deletionProofs",
+];
+const EXECUTION_RESULT_FIELDS: [&str; 11] = [
+    "l1DataFee",
+    "gas",
+    "failed",
+    "returnValue",
+    "from",
+    "to",
+    "accountAfter",
+    "accountCreated",
+    "poseidonCodeHash",
+    "byteCode",
+    "structLogs",
+];
+// Some clients emit `null` instead of an empty array.
+const ARRAY_FIELDS: [&str; 6] = [
+    "transactions",
+    "executionResults",
+    "txStorageTraces",
+    "deletionProofs",
+    "accountAfter",
+    "structLogs",
+];
+
+/// Canonicalize the known client-specific differences of `BlockTrace` JSON (field casing, `null`
+/// arrays and JSON-RPC wrapper), then deserialize it.
+pub fn normalize_block_trace(mut value: Value) -> Result<BlockTrace> {
+    if value.get("header").is_none() {
+        if let Some(result) = value.get_mut("result").map(Value::take) {
+            value = result;
+        }
+    }
+
+    normalize_fields(&mut value, &BLOCK_TRACE_FIELDS);
+    if let Some(storage_trace) = value.get_mut("storageTrace") {
+        normalize_fields(storage_trace, &STORAGE_TRACE_FIELDS);
+    }
+    if let Some(Value::Array(storage_traces)) = value.get_mut("txStorageTraces") {
+        storage_traces
+            .iter_mut()
+            .for_each(|t| normalize_fields(t, &STORAGE_TRACE_FIELDS));
+    }
+    if let Some(Value::Array(results)) = value.get_mut("executionResults") {
+        results
+            .iter_mut()
+            .for_each(|r| normalize_fields(r, &EXECUTION_RESULT_FIELDS));
+    }
+
+    Ok(serde_json::from_value(value)?)
+}
+
+// Rename keys to the canonical field names (case and underscore insensitive), and replace `null`
+// arrays with empty ones.
+fn normalize_fields(value: &mut Value, fields: &[&str]) {
+    let obj = match value.as_object_mut() {
+        Some(obj) => obj,
+        None => return,
+    };
+
+    for field in fields {
+        if !obj.contains_key(*field) {
+            let key = obj
+                .keys()
+                .find(|k| k.replace('_', "").eq_ignore_ascii_case(field))
+                .cloned();
+            if let Some(key) = key {
+                let v = obj.remove(&key).unwrap();
+                obj.insert(field.to_string(), v);
+            }
+        }
+
+        if ARRAY_FIELDS.contains(field) && obj.get(*field).map_or(false, Value::is_null) {
+            obj.insert(field.to_string(), Value::Array(vec![]));
+        }
+    }
+}
+
+pub fn read_env_var<T: Clone + FromStr>(var_name: &'static str, default: T) -> T {
+    std::env::var(var_name)
+        .map(|s| s.parse::<T>().unwrap_or_else(|_| default.clone()))
+        .unwrap_or(default)
+}
+
+/// Read a bool flag from env, which is set by `1` or `true`.
+pub fn read_env_flag(var_name: &'static str) -> bool {
+    matches!(read_env_var(var_name, String::new()).as_str(), "1" | "true")
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct BatchMetric {
+    pub num_block: usize,
+    pub num_tx: usize,
+    pub num_step: usize,
+}
+
+impl std::iter::Sum for BatchMetric {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |acc, m| Self {
+            num_block: acc.num_block + m.num_block,
+            num_tx: acc.num_tx + m.num_tx,
+            num_step: acc.num_step + m.num_step,
+        })
+    }
+}
+
+pub fn metric_of_witness_block(block: &Block<Fr>) -> BatchMetric {
+    BatchMetric {
+        num_block: block.context.ctxs.len(),
+        num_tx: block.txs.len(),
+        num_step: block.txs.iter().map(|tx| tx.steps.len()).sum::<usize>(),
+    }
+}
+
+/// Roughly estimate the time to prove the witness block at degree, as a fixed cost plus a cost per
+/// row of the max used rows and the domain. It grows monotonically with the witness. The constants
+/// could be calibrated by env `PROVING_TIME_BASE_MS` and `PROVING_TIME_PER_ROW_NS`.
+pub fn estimate_proving_time(block: &Block<Fr>, degree: u32) -> Duration {
+    let rows = calculate_row_usage_of_witness_block(block)
+        .map(|rows| rows.into_iter().max().unwrap_or_default())
+        .unwrap_or_else(|e| {
+            log::warn!("failed to calculate row usage, estimate by domain only: {e}");
+            0
+        });
+
+    let rows = rows as u64 + (1u64 << degree);
+    let estimated = Duration::from_millis(*PROVING_TIME_BASE_MS)
+        + Duration::from_nanos(rows.saturating_mul(*PROVING_TIME_PER_ROW_NS));
+    log::debug!("estimated proving time of {rows} rows at degree {degree}: {estimated:?}");
+    estimated
+}
+
+/// Log the metric as a single line with a stable key, e.g.
+/// `batch_metric {"num_block":3,"num_tx":40,"num_step":120345}`, for log ingestion.
+pub fn log_metric(metric: &BatchMetric) {
+    match serde_json::to_string(metric) {
+        Ok(json) => log::info!("batch_metric {json}"),
+        Err(e) => log::warn!("failed to serialize batch metric {metric:?}: {e}"),
+    }
+}
+
+/// Metric of each block in the witness block, in the order of block number.
+pub fn per_block_metrics(block: &Block<Fr>) -> Vec<BatchMetric> {
+    block
+        .context
+        .ctxs
+        .keys()
+        .map(|number| {
+            let txs = block.txs.iter().filter(|tx| tx.block_number == *number);
+            BatchMetric {
+                num_block: 1,
+                num_tx: txs.clone().count(),
+                num_step: txs.map(|tx| tx.steps.len()).sum(),
+            }
+        })
+        .collect()
+}
+
+/// Build the witness of the traces, and check it passes `sanity_check_witness` and the per-block
+/// metrics sum to the chunk metric. It's an invariant check of the conversion path, e.g. over a
+/// trace corpus.
+pub fn verify_roundtrip(block_traces: &[BlockTrace]) -> Result<()> {
+    let block = block_traces_to_witness_block(block_traces)?;
+    sanity_check_witness(&block)?;
+
+    let chunk_metric = metric_of_witness_block(&block);
+    let per_block_sum: BatchMetric = per_block_metrics(&block).into_iter().sum();
+    if per_block_sum != chunk_metric {
+        bail!(
+            "sum of per-block metrics {per_block_sum:?} mismatches chunk metric {chunk_metric:?}"
+        );
+    }
+    if chunk_metric.num_block != block_traces.len() {
+        bail!(
+            "witness of {} blocks, but {} traces",
+            chunk_metric.num_block,
+            block_traces.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Run the pre-proving steps of a chunk without proving: the capacity check, witness build, and the
+/// sanity check if `WITNESS_SANITY` is set. Return the metric of the witness block.
+pub fn prove_dry_run(chunk_trace: Vec<BlockTrace>) -> Result<BatchMetric> {
+    let witness_block = chunk_trace_to_witness_block(chunk_trace)?;
+    let metric = metric_of_witness_block(&witness_block);
+    log::info!("dry run done: {metric:?}");
+
+    Ok(metric)
+}
+
+/// Error of block numbers which aren't contiguous in a chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContinuityError {
+    /// The block of the trace index has no number.
+    MissingNumber(usize),
+    /// The block number appears more than once, e.g. from overlapping trace sources.
+    Duplicate(u64),
+    Gap {
+        prev: u64,
+        next: u64,
+    },
+}
+
+impl std::fmt::Display for ContinuityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingNumber(index) => write!(f, "block trace {index} has no block number"),
+            Self::Duplicate(number) => write!(f, "duplicate block {number} in chunk"),
+            Self::Gap { prev, next } => write!(f, "block {next} doesn't follow block {prev}"),
+        }
+    }
+}
+
+impl std::error::Error for ContinuityError {}
+
+/// Check the block numbers of the chunk are contiguous and ascending. Duplicate blocks are
+/// reported as `ContinuityError::Duplicate` rather than a gap.
+pub fn validate_chunk_continuity(block_traces: &[BlockTrace]) -> Result<(), ContinuityError> {
+    let numbers = block_traces
+        .iter()
+        .enumerate()
+        .map(|(index, trace)| {
+            trace
+                .header
+                .number
+                .map(|n| n.as_u64())
+                .ok_or(ContinuityError::MissingNumber(index))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut seen = std::collections::HashSet::new();
+    if let Some(number) = numbers.iter().find(|n| !seen.insert(**n)) {
+        return Err(ContinuityError::Duplicate(*number));
+    }
+    for pair in numbers.windows(2) {
+        if pair[1] != pair[0] + 1 {
+            return Err(ContinuityError::Gap {
+                prev: pair[0],
+                next: pair[1],
+            });
+        }
+    }
+
+    Ok(())
+}
+
+/// Return the pre and post state roots of the chunk, and check the post root of each block is the
+/// pre root of the next one.
+pub fn state_root_transition(block_traces: &[BlockTrace]) -> Result<(H256, H256)> {
+    let (first, last) = match (block_traces.first(), block_traces.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => bail!("Empty chunk trace"),
+    };
+
+    for pair in block_traces.windows(2) {
+        let (prev, next) = (&pair[0], &pair[1]);
+        if prev.storage_trace.root_after != next.storage_trace.root_before {
+            bail!(
+                "state root of block {:?} is {:?}, but block {:?} starts from {:?}",
+                prev.header.number,
+                prev.storage_trace.root_after,
+                next.header.number,
+                next.storage_trace.root_before
+            );
+        }
+    }
+
+    Ok((
+        first.storage_trace.root_before,
+        last.storage_trace.root_after,
+    ))
+}
+
+pub fn metric_of_block_traces(block_traces: &[BlockTrace]) -> BatchMetric {
+    BatchMetric {
+        num_block: block_traces.len(),
+        num_tx: block_traces.iter().map(|b| b.transactions.len()).sum(),
+        num_step: block_traces
+            .iter()
+            .flat_map(|b| b.execution_results.iter())
+            .map(|r| r.exec_steps.len())
+            .sum(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TraceProvenance {
+    pub path: String,
+    // Hex encoded sha256 of the trace file.
+    pub sha256: String,
+    pub block_number: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BlockGas {
+    pub block_number: Option<u64>,
+    pub gas: u64,
+    pub fee: U256,
+}
+
+/// Gas used and tx fees (including L1 data fees) of a chunk.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GasSummary {
+    pub total_gas: u64,
+    pub total_fee: U256,
+    pub per_block: Vec<BlockGas>,
+}
+
+pub fn chunk_gas_summary(block_traces: &[BlockTrace]) -> GasSummary {
+    let per_block: Vec<_> = block_traces
+        .iter()
+        .map(|trace| {
+            let mut block = BlockGas {
+                block_number: trace.header.number.map(|n| n.as_u64()),
+                ..Default::default()
+            };
+            for (tx, result) in trace.transactions.iter().zip(&trace.execution_results) {
+                block.gas += result.gas;
+                block.fee += tx.gas_price * result.gas + result.l1_fee;
+            }
+            block
+        })
+        .collect();
+
+    GasSummary {
+        total_gas: per_block.iter().map(|b| b.gas).sum(),
+        total_fee: per_block.iter().fold(U256::zero(), |acc, b| acc + b.fee),
+        per_block,
+    }
+}
+
+/// Provenance of a proof, which records the input traces and the resolved config.
+#[derive(Debug, Serialize)]
+pub struct ProofManifest {
+    pub git_version: String,
+    pub created_at: String,
+    pub traces: Vec<TraceProvenance>,
+    pub metric: BatchMetric,
+    pub gas: GasSummary,
+    pub config: BTreeMap<String, u32>,
+}
+
+impl ProofManifest {
+    /// `trace_paths` are the files which `block_traces` are loaded from, in the same order.
+    pub fn new<P: AsRef<Path>>(trace_paths: &[P], block_traces: &[BlockTrace]) -> Result<Self> {
+        if trace_paths.len() != block_traces.len() {
+            bail!(
+                "{} trace paths, but {} block traces",
+                trace_paths.len(),
+                block_traces.len()
+            );
+        }
+
+        let traces = trace_paths
+            .iter()
+            .zip(block_traces.iter())
+            .map(|(path, trace)| {
+                Ok(TraceProvenance {
+                    path: path.as_ref().display().to_string(),
+                    sha256: hex::encode(Sha256::digest(fs::read(path)?)),
+                    block_number: trace.header.number.map(|n| n.as_u64()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let config = BTreeMap::from([
+            ("inner_degree".to_string(), *INNER_DEGREE),
+            ("chunk_degree".to_string(), *CHUNK_DEGREE),
+            ("agg_layer1_degree".to_string(), *AGG_LAYER1_DEGREE),
+            ("agg_layer2_degree".to_string(), *AGG_LAYER2_DEGREE),
+            ("agg_layer3_degree".to_string(), *AGG_LAYER3_DEGREE),
+            ("agg_layer4_degree".to_string(), *AGG_LAYER4_DEGREE),
+        ]);
+
+        Ok(Self {
+            git_version: GIT_VERSION.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            traces,
+            metric: metric_of_block_traces(block_traces),
+            gas: chunk_gas_summary(block_traces),
+            config,
+        })
+    }
+
+    /// Write as `manifest.json` in the output dir.
+    pub fn dump(&self, output_dir: &str) -> Result<()> {
+        let path = Path::new(output_dir).join("manifest.json");
+        fs::write(&path, serde_json::to_vec_pretty(self)?)?;
+        log::info!("wrote proof manifest to {path:?}");
+
+        Ok(())
+    }
+}
+
+/// Keccak hash of the public inputs the chunk circuit commits to, as
+/// `keccak(chain_id || prev_state_root || post_state_root || withdraw_root || data_hash)`.
+pub fn public_input_hash(block: &Block<Fr>) -> [u8; 32] {
+    ChunkHash::from_witness_block(block, false)
+        .public_input_hash()
+        .0
+}
+
+/// Public instances of the chunk circuit expected for the traces, as a single column of the high
+/// and low 128 bits of `public_input_hash`. Instead of a full witness build, only a light witness
+/// (without MPT and keccak) is built for the fields not in the traces, e.g. withdraw root.
+///
+/// The halves are the big-endian bytes of the hash, which the compressed chunk proof carries as
+/// the `CHUNK_DIGEST_LEN` bytes after the accumulator (see `decode_chunk_instances`).
+/// `test_expected_public_inputs` pins both against the circuit of a full witness build.
+pub fn expected_public_inputs(block_traces: &[BlockTrace]) -> Result<Vec<Vec<Fr>>> {
+    if block_traces.is_empty() {
+        bail!("no block traces to derive public inputs");
+    }
+
+    let opts = WitnessOptions {
+        build_keccak: false,
+        build_mpt: false,
+    };
+    let block = block_traces_to_witness_block_with_opts(block_traces, &opts)?;
+    let pi_hash = public_input_hash(&block);
+
+    // Big-endian halves to field elements.
+    let to_fr = |half: &[u8]| {
+        let mut repr = <Fr as PrimeField>::Repr::default();
+        repr[..16].copy_from_slice(half);
+        repr[..16].reverse();
+        Fr::from_repr(repr).unwrap()
+    };
+    Ok(vec![vec![to_fr(&pi_hash[..16]), to_fr(&pi_hash[16..])]])
+}
+
+/// Same as `chunk_trace_to_witness_block`, but borrow the chunk trace. Instead of truncating the
+/// trace, the blocks which fit in the circuit capacity are reported by `CapacityReport`.
+pub fn chunk_trace_to_witness_block_ref(
+    chunk_trace: &[BlockTrace],
+) -> Result<(Block<Fr>, CapacityReport), WitnessError> {
+    if chunk_trace.is_empty() {
+        return Err(WitnessError::EmptyChunk);
+    }
+
+    let report = batch_capacity_report(chunk_trace, CapacityPolicy::Trim)
+        .map_err(WitnessError::from_anyhow)?;
+    let witness_block = {
+        let _span = phase_span!("witness");
+        block_traces_to_witness_block(&chunk_trace[..report.num_block_kept])
+            .map_err(WitnessError::TraceToWitness)?
+    };
+    if *WITNESS_SANITY {
+        sanity_check_witness(&witness_block).map_err(WitnessError::TraceToWitness)?;
+    }
+
+    Ok((witness_block, report))
+}
+
+/// Intermediate counts of a witness build, for profiling.
+#[derive(Debug, Clone, Default)]
+pub struct WitnessBuildReport {
+    pub num_rw_rows: usize,
+    pub num_keccak_inputs: usize,
+    pub num_keccak_bytes: usize,
+    pub num_copy_rows: usize,
+    // Actual rows of each sub-circuit assigned from the built witness, i.e. what the estimator
+    // predicts. Empty if failed to calculate.
+    pub sub_circuit_rows: Vec<(&'static str, usize)>,
+    pub elapsed: Duration,
+}
+
+impl WitnessBuildReport {
+    pub fn new(block: &Block<Fr>, elapsed: Duration) -> Self {
+        let sub_circuit_rows = match calculate_row_usage_of_witness_block(block) {
+            Ok(rows) => SUB_CIRCUIT_NAMES.iter().copied().zip(rows).collect(),
+            Err(e) => {
+                log::warn!("failed to calculate row usage of witness block: {e:?}");
+                vec![]
+            }
+        };
+
+        Self {
+            num_rw_rows: block.rws.0.values().map(Vec::len).sum(),
+            num_keccak_inputs: block.keccak_inputs.len(),
+            num_keccak_bytes: block.keccak_inputs.iter().map(Vec::len).sum(),
+            // Same as the rows required by copy circuit.
+            num_copy_rows: block.copy_events.iter().map(|c| c.bytes.len() * 2).sum(),
+            sub_circuit_rows,
+            elapsed,
+        }
+    }
+}
+
+pub fn chunk_trace_to_witness_block(
+    chunk_trace: Vec<BlockTrace>,
+) -> Result<Block<Fr>, WitnessError> {
+    chunk_trace_to_witness_block_with_report(chunk_trace).map(|(block, _)| block)
+}
+
+/// Same as `chunk_trace_to_witness_block`, but retry up to `attempts` times with a short backoff if
+/// the error is transient (`WitnessError::is_transient`).
+pub fn chunk_trace_to_witness_block_retry(
+    chunk_trace: Vec<BlockTrace>,
+    attempts: usize,
+) -> Result<Block<Fr>, WitnessError> {
+    let attempts = attempts.max(1);
+    for attempt in 1..attempts {
+        match chunk_trace_to_witness_block(chunk_trace.clone()) {
+            Err(e) if e.is_transient() => {
+                log::warn!("witness build attempt {attempt}/{attempts} failed: {e}, retrying");
+                thread::sleep(Duration::from_millis(500 * attempt as u64));
+            }
+            result => return result,
+        }
+    }
+
+    log::info!("witness build attempt {attempts}/{attempts}");
+    chunk_trace_to_witness_block(chunk_trace)
+}
+
+/// Same as `chunk_trace_to_witness_block`, but also return the intermediate counts.
+pub fn chunk_trace_to_witness_block_with_report(
+    mut chunk_trace: Vec<BlockTrace>,
+) -> Result<(Block<Fr>, WitnessBuildReport), WitnessError> {
+    if chunk_trace.is_empty() {
+        return Err(WitnessError::EmptyChunk);
+    }
+
+    // Check if the trace exceeds the circuit capacity.
+    check_batch_capacity(&mut chunk_trace).map_err(WitnessError::from_anyhow)?;
+
+    let now = Instant::now();
+    let witness_block = {
+        let _span = phase_span!("witness");
+        block_traces_to_witness_block(&chunk_trace).map_err(WitnessError::TraceToWitness)?
+    };
+    let report = WitnessBuildReport::new(&witness_block, now.elapsed());
+    log::debug!("witness build report: {report:?}");
+    log_metric(&metric_of_witness_block(&witness_block));
+
+    if *WITNESS_SANITY {
+        sanity_check_witness(&witness_block).map_err(WitnessError::TraceToWitness)?;
+    }
+
+    Ok((witness_block, report))
+}
+
+/// Output dir of a run, which is removed on drop unless the run is marked as succeeded, or
+/// `KEEP_FAILED_OUTPUT=1`. An existing dir (e.g. set by `OUTPUT_DIR`) is never removed.
+#[derive(Debug)]
+pub struct OutputDir {
+    path: String,
+    created: bool,
+    succeeded: bool,
+}
+
+impl OutputDir {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn succeed(&mut self) {
+        self.succeeded = true;
+        PENDING_OUTPUT_DIR.lock().unwrap().take();
+    }
+}
+
+impl Drop for OutputDir {
+    fn drop(&mut self) {
+        if self.succeeded || !self.created {
+            return;
+        }
+
+        PENDING_OUTPUT_DIR.lock().unwrap().take();
+        remove_failed_output_dir(&self.path);
+    }
+}
+
+fn remove_failed_output_dir(path: &str) {
+    if *KEEP_FAILED_OUTPUT {
+        log::warn!("run failed, keep output dir {path}");
+        return;
+    }
+
+    log::warn!("run failed, remove output dir {path}");
+    if let Err(e) = fs::remove_dir_all(path) {
+        log::error!("failed to remove output dir {path}: {e}");
+    }
+}
+
+// Return the output dir.
+pub fn init_env_and_log(id: &str) -> String {
+    init_output_dir_and_log(id).0
+}
+
+/// Same as `init_env_and_log`, but return a handle which cleans up the output dir if the run
+/// fails or is cancelled before calling `OutputDir::succeed`.
+pub fn init_env_and_log_with_cleanup(id: &str) -> OutputDir {
+    let (path, created) = init_output_dir_and_log(id);
+    if created {
+        *PENDING_OUTPUT_DIR.lock().unwrap() = Some(path.clone());
+    }
+    OutputDir {
+        path,
+        created,
+        succeeded: false,
+    }
+}
+
+fn init_output_dir_and_log(id: &str) -> (String, bool) {
+    dotenv::dotenv().ok();
+    Lazy::force(&START_TIME);
+    let (output_dir, created) = create_output_dir(id);
+    OUTPUT_DIR.get_or_init(|| output_dir.clone());
+
+    LOGGER.call_once(|| {
+        // TODO: cannot support complicated `RUST_LOG` for now.
+        let log_level = read_env_var("RUST_LOG", "INFO".to_string());
+        let log_level = LevelFilter::from_str(&log_level).unwrap_or(LevelFilter::Info);
+        // Levels of the log file and stderr could be set separately, default as `RUST_LOG`.
+        let read_level = |var_name| {
+            LevelFilter::from_str(&read_env_var(var_name, String::new())).unwrap_or(log_level)
+        };
+        let file_level = read_level("LOG_FILE_LEVEL");
+        let console_level = read_level("LOG_CONSOLE_LEVEL");
+
+        // Could set `LOG_FILE_NAME=log_{id}.txt` to separate the log files of runs sharing the
+        // same output dir.
+        let log_file_name =
+            read_env_var("LOG_FILE_NAME", "log.txt".to_string()).replace("{id}", id);
+        let mut log_file_path = PathBuf::from(output_dir.clone());
+        log_file_path.push(log_file_name);
+        // Prepend the prefix to every line, e.g. a tenant id for routing.
+        let prefix = LOG_PREFIX
+            .get()
+            .cloned()
+            .unwrap_or_else(|| read_env_var("LOG_PREFIX", String::new()));
+        let encoder = || -> Box<dyn Encode> {
+            let pattern = PatternEncoder::new("{d} {l} {t} - {m}{n}");
+            if prefix.is_empty() {
+                Box::new(pattern)
+            } else {
+                Box::new(PrefixEncoder {
+                    prefix: format!("{prefix} "),
+                    inner: pattern,
+                })
+            }
+        };
+
+        // Sync every line to disk by `LOG_FLUSH_EVERY_LINE=1`, e.g. to debug a hang.
+        let log_file: Box<dyn Append> = if read_env_flag("LOG_FLUSH_EVERY_LINE") {
+            Box::new(SyncFileAppender::new(&log_file_path, encoder()).unwrap())
+        } else {
+            Box::new(
+                FileAppender::builder()
+                    .encoder(encoder())
+                    .build(log_file_path)
+                    .unwrap(),
+            )
+        };
+
+        let stderr = ConsoleAppender::builder()
+            .encoder(encoder())
+            .target(Target::Stderr)
+            .build();
+
+        let config = Config::builder()
+            .appenders([
+                Appender::builder()
+                    .filter(Box::new(ThresholdFilter::new(file_level)))
+                    .build("log-file", log_file),
+                Appender::builder()
+                    .filter(Box::new(ThresholdFilter::new(console_level)))
+                    .build("stderr", Box::new(stderr)),
+            ])
+            .build(
+                Root::builder()
+                    .appender("log-file")
+                    .appender("stderr")
+                    .build(file_level.max(console_level)),
+            )
+            .unwrap();
+
+        log4rs::init_config(config).unwrap();
+        install_panic_hook();
+
+        log::info!("git version {}", GIT_VERSION);
+        log::info!("cpu features: {:?}", cpu_features());
+    });
+
+    (output_dir, created)
+}
+
+// Encoder which writes the prefix verbatim before each record, since the prefix could contain the
+// metacharacters of log4rs patterns.
+#[derive(Debug)]
+struct PrefixEncoder {
+    prefix: String,
+    inner: PatternEncoder,
+}
+
+impl Encode for PrefixEncoder {
+    fn encode(&self, w: &mut dyn log4rs::encode::Write, record: &log::Record) -> Result<()> {
+        w.write_all(self.prefix.as_bytes())?;
+        self.inner.encode(w, record)
+    }
+}
+
+// Log file appender which syncs each record to disk. `FileAppender` only flushes to the OS, so the
+// last lines could be lost if the machine goes down.
+#[derive(Debug)]
+struct SyncFileAppender {
+    file: std::sync::Mutex<File>,
+    encoder: Box<dyn Encode>,
+}
+
+impl SyncFileAppender {
+    fn new(path: &Path, encoder: Box<dyn Encode>) -> Result<Self> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        Ok(Self {
+            file: std::sync::Mutex::new(file),
+            encoder,
+        })
+    }
+}
+
+impl Append for SyncFileAppender {
+    fn append(&self, record: &log::Record) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        self.encoder.encode(&mut SimpleWriter(&mut *file), record)?;
+        file.sync_data()?;
+
+        Ok(())
+    }
+
+    fn flush(&self) {}
+}
+
+/// CPU features the proving performance depends on, e.g. to explain a slow VM.
+#[derive(Debug, Clone, Copy)]
+pub struct CpuFeatures {
+    pub avx2: bool,
+    pub adx: bool,
+    pub bmi2: bool,
+    pub num_cores: usize,
+}
+
+pub fn cpu_features() -> CpuFeatures {
+    #[cfg(target_arch = "x86_64")]
+    let (avx2, adx, bmi2) = (
+        is_x86_feature_detected!("avx2"),
+        is_x86_feature_detected!("adx"),
+        is_x86_feature_detected!("bmi2"),
+    );
+    #[cfg(not(target_arch = "x86_64"))]
+    let (avx2, adx, bmi2) = (false, false, false);
+
+    CpuFeatures {
+        avx2,
+        adx,
+        bmi2,
+        num_cores: thread::available_parallelism().map_or(1, usize::from),
+    }
+}
+
+// Route panics through the logger, so they're captured by both appenders, then call the default
+// hook to keep the panicking behavior.
+fn install_panic_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let backtrace = std::backtrace::Backtrace::force_capture();
+        log::error!("{info}\nbacktrace:\n{backtrace}");
+        log::logger().flush();
+        default_hook(info);
+    }));
+}
+
+/// Set the prefix of log lines, which overrides env `LOG_PREFIX`. It must be called before
+/// `init_env_and_log`, and only once.
+pub fn set_log_prefix(prefix: &str) -> Result<()> {
+    if LOGGER.is_completed() {
+        bail!("log prefix must be set before the logger is initialized");
+    }
+    if LOG_PREFIX.set(prefix.to_string()).is_err() {
+        bail!("log prefix is already set");
+    }
+
+    Ok(())
+}
+
+/// Output dir of the first `init_env_and_log` call, if any.
+pub fn output_dir() -> Option<&'static str> {
+    OUTPUT_DIR.get().map(String::as_str)
+}
+
+/// Check the output dir has a complete artifact set of a run: `manifest.json`, the log file, and
+/// for each `{name}_full_proof.json` its vk, proof and instances. Return error listing the missing
+/// or empty files.
+pub fn validate_output_layout(dir: &str) -> Result<()> {
+    let mut missing = vec![];
+    let mut check = |path: PathBuf| match metadata(&path) {
+        Ok(m) if m.len() > 0 => {}
+        Ok(_) => missing.push(format!("{} (empty)", path.display())),
+        Err(_) => missing.push(path.display().to_string()),
+    };
+
+    let dir_path = Path::new(dir);
+    check(dir_path.join("manifest.json"));
+
+    let log_file_name = read_env_var("LOG_FILE_NAME", "log.txt".to_string());
+    if log_file_name.contains("{id}") {
+        let pattern = format!("{dir}/{}", log_file_name.replace("{id}", "*"));
+        match glob::glob(&pattern)?.next() {
+            Some(path) => check(path?),
+            None => check(PathBuf::from(pattern)),
+        }
+    } else {
+        check(dir_path.join(log_file_name));
+    }
+
+    let proofs = glob::glob(&format!("{dir}/*_full_proof.json"))?.collect::<Vec<_>>();
+    if proofs.is_empty() {
+        check(dir_path.join("*_full_proof.json"));
+    }
+    for path in proofs {
+        let path = path?;
+        let name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.strip_suffix("_full_proof.json"))
+            .unwrap()
+            .to_string();
+        check(path);
+        check(dir_path.join(format!("{name}.vkey")));
+        check(dir_path.join(format!("{name}_proof.data")));
+        check(dir_path.join(format!("{name}_instances.data")));
+    }
+
+    if !missing.is_empty() {
+        bail!(
+            "incomplete output dir {dir}, missing: {}",
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+// Return the output dir and whether it's newly created.
+fn create_output_dir(id: &str) -> (String, bool) {
+    let mode = read_env_var("MODE", "multi".to_string());
+    let output = read_env_var(
+        "OUTPUT_DIR",
+        format!(
+            "{}_output_{}_{}",
+            id,
+            mode,
+            Utc::now().format("%Y%m%d_%H%M%S")
+        ),
+    );
+
+    let output_dir = PathBuf::from_str(&output).unwrap();
+    let created = !output_dir.exists();
+    fs::create_dir_all(output_dir).unwrap();
+
+    (output, created)
+}
+
+// Expected params file length:
+//   len: 4 bytes
+//   g: 2**DEGREE g1 points, each 32 bytes(256bits)
+//   g_lagrange: 2**DEGREE g1 points, each 32 bytes(256bits)
+//   g2: g2 point, 64 bytes
+//   s_g2: g2 point, 64 bytes
+fn params_file_len(degree: u32, serde_fmt: SerdeFormat) -> u64 {
+    let g1_num = 2 * (1 << degree);
+    let g2_num = 2;
+
+    let g1_bytes_len = g1_point_len(serde_fmt) as u64;
+    let g2_bytes_len = 2 * g1_bytes_len;
+    4 + g1_num * g1_bytes_len + g2_num * g2_bytes_len
+}
+
+/// List the degrees of valid params files (named as `params{degree}`) in the dir, sorted.
+/// Files with an invalid length are logged as suspect and excluded.
+pub fn available_param_degrees(params_dir: &str) -> Result<Vec<u32>> {
+    let mut degrees = vec![];
+    for entry in fs::read_dir(params_dir)? {
+        let path = entry?.path();
+        let degree = match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_prefix("params"))
+            .and_then(|degree| degree.parse::<u32>().ok())
+        {
+            Some(degree) => degree,
+            None => continue,
+        };
+        // Skip the file names which couldn't be a real degree.
+        if degree > MAX_PARAMS_DEGREE || !path.is_file() {
+            continue;
+        }
+
+        let file_size = metadata(&path)?.len();
+        let expected_len = params_file_len(degree, resolve_serde_format());
+        if file_size != expected_len {
+            log::warn!(
+                "suspect params file {path:?}: len {file_size}, expected {expected_len} for degree {degree}"
+            );
+            continue;
+        }
+
+        degrees.push(degree);
+    }
+
+    degrees.sort_unstable();
+    Ok(degrees)
+}
+
+// Hex encoded sha256 of a file, without reading it into memory.
+fn file_sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut BufReader::new(File::open(path)?), &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Consolidate the params files of several dirs into `dest`. For each degree, the first valid file
+/// (as `available_param_degrees`) is copied atomically, unless `dest` already has it. Copies with
+/// different contents are logged as conflicts and skipped. Return the degrees in `dest` merged or
+/// confirmed, sorted.
+pub fn merge_params_dirs(srcs: &[PathBuf], dest: &Path) -> Result<Vec<u32>> {
+    fs::create_dir_all(dest)?;
+    let dest_dir = dest.to_str().unwrap();
+
+    // degree -> sha256 of the file in dest
+    let mut merged = BTreeMap::new();
+    for degree in available_param_degrees(dest_dir)? {
+        let path = PathBuf::from(param_path_for_degree(dest_dir, degree));
+        merged.insert(degree, file_sha256(&path)?);
+    }
+
+    for src in srcs {
+        let src_dir = src.to_str().unwrap();
+        for degree in available_param_degrees(src_dir)? {
+            let path = PathBuf::from(param_path_for_degree(src_dir, degree));
+            let sha256 = file_sha256(&path)?;
+            if let Some(merged_sha256) = merged.get(&degree) {
+                if merged_sha256 != &sha256 {
+                    log::warn!(
+                        "conflicting params{degree} in {src_dir}: sha256 {sha256}, but merged {merged_sha256}"
+                    );
+                }
+                continue;
+            }
+
+            // Copy to a temp file then rename, so `dest` never has a partial params file.
+            let dest_path = PathBuf::from(param_path_for_degree(dest_dir, degree));
+            let tmp_path = dest_path.with_extension("tmp");
+            fs::copy(&path, &tmp_path)?;
+            fs::rename(&tmp_path, &dest_path)?;
+            log::info!("merged params{degree} from {src_dir}");
+
+            merged.insert(degree, sha256);
+        }
+    }
+
+    Ok(merged.into_keys().collect())
+}
+
+// Min degree whose row limit covers the rows.
+/// Load the traces (`*.json`) of dir, chunk them as the circuit capacity of `INNER_DEGREE` (by the
+/// limits of the capacity profile), and return the min degree which covers every chunk.
+pub fn min_degree_for_traces(dir: &str) -> Result<u32> {
+    let mut traces = glob::glob(&format!("{dir}/**/*.json"))?
+        .map(|path| {
+            let path = path?;
+            let trace = block_trace_from_value(serde_json::from_slice(&fs::read(&path)?)?)?;
+            Ok(trace)
+        })
+        .collect::<Result<Vec<BlockTrace>>>()?;
+    if traces.is_empty() {
+        bail!("no traces found in {dir}");
+    }
+    traces.sort_by_key(|trace| trace.header.number);
+
+    let profile = capacity_profile();
+    // Rows of each chunk, accumulated by blocks in the same way as `check_batch_capacity`.
+    let mut chunk_rows = vec![];
+    let mut acc: Vec<usize> = vec![];
+    let mut num_block = 0;
+    for trace in &traces {
+        let usage = calculate_row_usage_of_trace(trace)?;
+        let new_acc: Vec<usize> = if acc.is_empty() {
+            usage.clone()
+        } else {
+            acc.iter().zip(usage.iter()).map(|(a, u)| a + u).collect()
+        };
+
+        if !acc.is_empty()
+            && (!profile.fits(&new_acc, *INNER_DEGREE) || num_block >= profile.block_limit())
+        {
+            chunk_rows.push(acc);
+            acc = usage;
+            num_block = 1;
+        } else {
+            acc = new_acc;
+            num_block += 1;
+        }
+    }
+    chunk_rows.push(acc);
+
+    let mut min_degree = 0;
+    for (idx, rows) in chunk_rows.iter().enumerate() {
+        let degree = profile.min_degree(rows);
+        if degree > *INNER_DEGREE {
+            log::warn!("chunk {idx} of {rows:?} rows requires degree {degree}");
+        }
+        min_degree = min_degree.max(degree);
+    }
+    log::info!(
+        "min degree {min_degree} for {} traces in {} chunks",
+        traces.len(),
+        chunk_rows.len()
+    );
+
+    Ok(min_degree)
+}
+
+/// Check the params of chunk circuit and aggregation circuit are consistent: aggregation degree
+/// is not less than chunk degree, lengths are valid for the degrees, and both are from the same
+/// setup.
+pub fn assert_aggregation_params(
+    chunk_params: &ParamsKZG<Bn256>,
+    agg_params: &ParamsKZG<Bn256>,
+) -> Result<()> {
+    let (chunk_degree, agg_degree) = (chunk_params.k(), agg_params.k());
+    if agg_degree < chunk_degree {
+        bail!("aggregation params of degree {agg_degree} is less than chunk params of degree {chunk_degree}");
+    }
+
+    for params in [chunk_params, agg_params] {
+        if params.get_g().len() as u64 != params.n() {
+            bail!(
+                "invalid params of degree {}: {} g1 points, expected {}",
+                params.k(),
+                params.get_g().len(),
+                params.n()
+            );
+        }
+    }
+
+    // Downsized params share the leading g1 points and g2 points.
+    if chunk_params.g2() != agg_params.g2()
+        || chunk_params.s_g2() != agg_params.s_g2()
+        || chunk_params.get_g()[0] != agg_params.get_g()[0]
+    {
+        bail!("chunk params of degree {chunk_degree} and aggregation params of degree {agg_degree} are from different setups");
+    }
+
+    Ok(())
+}
+
+pub fn param_path_for_degree(params_dir: &str, degree: u32) -> String {
+    format!("{params_dir}/params{degree}")
+}
+
+/// RNG used for proving, either reproducible from a seed or seeded by OS.
+#[derive(Clone, Debug)]
+pub enum ProverRng {
+    Deterministic(XorShiftRng),
+    Secure(StdRng),
+}
+
+impl RngCore for ProverRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            Self::Deterministic(rng) => rng.next_u32(),
+            Self::Secure(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Self::Deterministic(rng) => rng.next_u64(),
+            Self::Secure(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            Self::Deterministic(rng) => rng.fill_bytes(dest),
+            Self::Secure(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            Self::Deterministic(rng) => rng.try_fill_bytes(dest),
+            Self::Secure(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+impl SeedableRng for ProverRng {
+    type Seed = [u8; 16];
+
+    fn from_seed(seed: Self::Seed) -> Self {
+        Self::Deterministic(XorShiftRng::from_seed(seed))
+    }
+}
+
+static ZERO_SEED_WARNING: Once = Once::new();
+
+/// Generate a reproducible RNG with the zero seed.
+pub fn gen_rng() -> ProverRng {
+    // Tests are usually run in debug builds, only warn in release builds.
+    if !cfg!(debug_assertions) {
+        ZERO_SEED_WARNING.call_once(|| {
+            log::warn!(
+                "Deterministic randomness with zero seed is in use, \
+                use `gen_secure_rng` for production proving"
+            );
+        });
+    }
+
+    ProverRng::from_seed([0u8; 16])
+}
+
+/// Generate a reproducible RNG with a distinct seed derived from the label.
+pub fn gen_rng_named(label: &str) -> ProverRng {
+    let hash = Blake2s256::digest(label.as_bytes());
+    let mut seed = [0u8; 16];
+    seed.copy_from_slice(&hash[..16]);
+    ProverRng::from_seed(seed)
+}
+
+/// Generate a RNG seeded by OS.
+pub fn gen_secure_rng() -> ProverRng {
+    ProverRng::Secure(StdRng::from_entropy())
+}
+
+#[derive(Debug, Serialize)]
+pub struct ShutdownSummary {
+    pub git_version: String,
+    pub elapsed_ms: u64,
+    // Peak resident memory (VmHWM) in KB, only on linux.
+    pub peak_memory_kb: Option<u64>,
+}
+
+impl ShutdownSummary {
+    fn new() -> Self {
+        #[cfg(target_os = "linux")]
+        let peak_memory_kb = procfs::process::Process::myself()
+            .and_then(|p| p.status())
+            .ok()
+            .and_then(|s| s.vmhwm);
+        #[cfg(not(target_os = "linux"))]
+        let peak_memory_kb = None;
+
+        Self {
+            git_version: GIT_VERSION.to_string(),
+            elapsed_ms: START_TIME.elapsed().as_millis() as u64,
+            peak_memory_kb,
+        }
+    }
+}
+
+/// Write the peak memory and timing summary to `shutdown.json` of the output dir, and flush the
+/// log appenders.
+pub fn shutdown() {
+    let summary = ShutdownSummary::new();
+    log::info!("shutdown: {:?}", summary);
+
+    if let Some(output_dir) = output_dir() {
+        let path = Path::new(output_dir).join("shutdown.json");
+        let result = serde_json::to_vec_pretty(&summary)
+            .map_err(anyhow::Error::from)
+            .and_then(|buf| fs::write(&path, buf).map_err(Into::into));
+        if let Err(e) = result {
+            log::error!("failed to write {}: {}", path.display(), e);
+        }
+    }
+
+    log::logger().flush();
+}
+
+/// Call `shutdown` on SIGINT or SIGTERM, then clean up the pending output dir of
+/// `init_env_and_log_with_cleanup` as a cancelled run and exit.
+pub fn install_shutdown_handler() -> Result<()> {
+    ctrlc::set_handler(|| {
+        log::warn!("received termination signal");
+        shutdown();
+        if let Some(path) = PENDING_OUTPUT_DIR.lock().unwrap().take() {
+            remove_failed_output_dir(&path);
+            log::logger().flush();
+        }
+        std::process::exit(130);
+    })?;
+
+    Ok(())
+}
+
+/// Log the phase with the elapsed time since `init_env_and_log`, as `[+12.3s] desc`.
+pub fn log_phase(desc: &str) {
+    log::info!("{} {desc}", elapsed_annotation());
 }
 
-pub fn gen_rng() -> impl Rng + Send {
-    let seed = [0u8; 16];
-    XorShiftRng::from_seed(seed)
+fn elapsed_annotation() -> String {
+    format!("[+{:.1}s]", START_TIME.elapsed().as_secs_f64())
 }
 
 pub fn tick(desc: &str) {
@@ -201,7 +2330,8 @@ pub fn tick(desc: &str) {
     #[cfg(not(target_os = "linux"))]
     let memory = 0;
     log::debug!(
-        "memory usage when {}: {:?}GB",
+        "{} memory usage when {}: {:?}GB",
+        elapsed_annotation(),
         desc,
         memory / 1024 / 1024 / 1024
     );
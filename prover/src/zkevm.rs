@@ -1,8 +1,14 @@
 mod capacity_checker;
 pub mod circuit;
+mod gas_check;
 mod prover;
+mod replay;
 mod verifier;
 
 pub use self::prover::Prover;
 pub use capacity_checker::CircuitCapacityChecker;
-pub use verifier::Verifier;
+pub use gas_check::{check_trace_gas_consistency, GasCheckOutcome, GasMismatch};
+pub use replay::{replay_block_trace, ReplayMismatch, ReplayOutcome};
+pub use verifier::{
+    batch_verify_chunk_proofs, verify_batch_proof, verify_batch_proof_with_inputs, Verifier,
+};
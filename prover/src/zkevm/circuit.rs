@@ -5,15 +5,37 @@ use types::eth::BlockTrace;
 use zkevm_circuits::witness;
 
 mod builder;
+mod diff;
+mod estimator;
+mod export;
+mod memory;
+mod profile;
+mod spill;
 mod super_circuit;
 pub use super_circuit::SuperCircuit;
 
 use crate::utils::read_env_var;
 
-pub use self::builder::{
-    block_traces_to_witness_block, block_traces_to_witness_block_with_updated_state,
-    calculate_row_usage_of_trace, calculate_row_usage_of_witness_block, check_batch_capacity,
-    update_state, SUB_CIRCUIT_NAMES,
+pub use self::{
+    builder::{
+        batch_capacity_report, block_traces_to_witness_block,
+        block_traces_to_witness_block_with_opts, block_traces_to_witness_block_with_updated_state,
+        calculate_row_usage_of_trace, calculate_row_usage_of_witness_block,
+        capacity_estimate_error, check_batch_capacity, check_batch_capacity_with_policy,
+        opcode_capacity_attribution, sanity_check_witness, select_degree_for_capacity,
+        streaming_capacity, update_state, validate_keccak_inputs, witness_blocks_from_traces,
+        CapacityPolicy, CapacityReport, CircuitSelection, WitnessError, WitnessOptions,
+        SUB_CIRCUIT_NAMES,
+    },
+    diff::{diff_witness_blocks, RowDiff, WitnessDiff},
+    estimator::{calibrate_estimator, estimate_capacity, EstimatorCoefficients},
+    export::{export_witness_tables, ExportFormat},
+    memory::{
+        calibrate_witness_memory, estimated_witness_bytes, estimated_witness_bytes_with,
+        max_blocks_for_memory, WitnessMemoryCoefficients,
+    },
+    profile::CapacityProfile,
+    spill::{build_witness_spill_keccak, KeccakInputs, SpilledWitness},
 };
 
 ////// params for degree = 19 ////////////
@@ -28,7 +50,7 @@ const MAX_EXP_STEPS: usize = 10_000;
 
 ////// params for degree = 20 ////////////
 const MAX_TXS: usize = 32;
-const MAX_INNER_BLOCKS: usize = 100;
+pub(crate) const MAX_INNER_BLOCKS: usize = 100;
 const MAX_EXP_STEPS: usize = 10_000;
 const MAX_CALLDATA: usize = 400_000;
 const MAX_BYTECODE: usize = 400_000;
@@ -38,6 +60,34 @@ const MAX_RWS: usize = 1_000_000;
 
 static CHAIN_ID: Lazy<u64> = Lazy::new(|| read_env_var("CHAIN_ID", 0x82751));
 static AUTO_TRUNCATE: Lazy<bool> = Lazy::new(|| read_env_var("AUTO_TRUNCATE", true));
+// Memory budget (in bytes) of witness building, 0 means unlimited.
+static MAX_WITNESS_MEMORY: Lazy<usize> = Lazy::new(|| read_env_var("MAX_WITNESS_MEMORY", 0));
+// Json file of the witness memory coefficients, e.g. dumped after `calibrate_witness_memory`.
+static WITNESS_MEMORY: Lazy<WitnessMemoryCoefficients> = Lazy::new(|| {
+    let path = read_env_var("WITNESS_MEMORY_COEFFICIENTS", String::new());
+    if path.is_empty() {
+        return WitnessMemoryCoefficients::default();
+    }
+    WitnessMemoryCoefficients::from_json_file(&path).unwrap_or_else(|e| {
+        log::warn!("failed to load witness memory coefficients {path}, use the default: {e}");
+        WitnessMemoryCoefficients::default()
+    })
+});
+// Capacity profile of the chain, e.g. `mainnet` or `sepolia`.
+static CAPACITY_PROFILE: Lazy<CapacityProfile> = Lazy::new(|| {
+    let name = read_env_var("CAPACITY_PROFILE", "conservative".to_string());
+    let profile = CapacityProfile::from_name(&name).unwrap_or_else(|e| {
+        log::warn!("{e}, use the default capacity profile");
+        CapacityProfile::default()
+    });
+    log::info!("capacity profile: {}", profile.name);
+    profile
+});
+
+/// The capacity profile selected by env `CAPACITY_PROFILE`.
+pub fn capacity_profile() -> &'static CapacityProfile {
+    &CAPACITY_PROFILE
+}
 
 /// A target circuit trait is a wrapper of inner circuit, with convenient APIs for building
 /// circuits from traces.
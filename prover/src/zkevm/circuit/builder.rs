@@ -1,20 +1,27 @@
 use super::{
-    TargetCircuit, AUTO_TRUNCATE, CHAIN_ID, MAX_BYTECODE, MAX_CALLDATA, MAX_EXP_STEPS,
-    MAX_INNER_BLOCKS, MAX_KECCAK_ROWS, MAX_MPT_ROWS, MAX_RWS, MAX_TXS,
+    estimate_capacity, max_blocks_for_memory, TargetCircuit, AUTO_TRUNCATE, CAPACITY_PROFILE,
+    CHAIN_ID, MAX_BYTECODE, MAX_CALLDATA, MAX_EXP_STEPS, MAX_INNER_BLOCKS, MAX_KECCAK_ROWS,
+    MAX_MPT_ROWS, MAX_RWS, MAX_TXS, MAX_WITNESS_MEMORY,
 };
 use crate::config::INNER_DEGREE;
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use bus_mapping::{
     circuit_input_builder::{self, BlockHead, CircuitInputBuilder, CircuitsParams},
     state_db::{Account, CodeDB, StateDB},
 };
 use eth_types::{evm_types::opcode_ids::OpcodeId, ToAddress, H256};
-use ethers_core::types::{Bytes, U256};
+use ethers_core::{
+    types::{Bytes, U256},
+    utils::keccak256,
+};
 use halo2_proofs::halo2curves::bn256::Fr;
 use is_even::IsEven;
 use itertools::Itertools;
 use mpt_zktrie::state::ZktrieState;
-use std::{collections::hash_map::Entry, time::Instant};
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashSet},
+    time::Instant,
+};
 use types::eth::{BlockTrace, EthBlock, ExecStep};
 use zkevm_circuits::{
     evm_circuit::witness::{block_apply_mpt_state, block_convert, Block},
@@ -51,9 +58,154 @@ pub fn calculate_row_usage_of_witness_block(witness_block: &Block<Fr>) -> Result
     Ok(rows)
 }
 
+/// Estimate the rows contributed by each opcode in the witness block, as the sum of EVM circuit
+/// step height and the number of RW rows of every step. The steps without opcode (e.g. BeginTx)
+/// are skipped.
+pub fn opcode_capacity_attribution(witness_block: &Block<Fr>) -> BTreeMap<OpcodeId, usize> {
+    let mut attribution = BTreeMap::new();
+    for step in witness_block.txs.iter().flat_map(|tx| tx.steps.iter()) {
+        if let Some(opcode) = step.opcode {
+            let rows = step.execution_state.get_step_height() + step.rw_indices.len();
+            *attribution.entry(opcode).or_insert(0) += rows;
+        }
+    }
+
+    log::debug!("opcode capacity attribution: {:?}", attribution);
+    attribution
+}
+
+/// Failures of building the witness block of a chunk.
+#[derive(Debug)]
+pub enum WitnessError {
+    EmptyChunk,
+    TooManyBlocks {
+        num_block: usize,
+        max_block: usize,
+    },
+    /// Rows of the leading blocks up to `block_index` exceed the row limit.
+    CapacityOverflow {
+        block_index: usize,
+        rows: usize,
+        row_limit: usize,
+    },
+    /// Only `num_block_fit` of the blocks fit in the witness memory budget.
+    MemoryBudget {
+        budget: usize,
+        num_block_fit: usize,
+        num_block: usize,
+    },
+    /// Failed to convert the traces to witness, or the witness is invalid.
+    TraceToWitness(anyhow::Error),
+}
+
+impl WitnessError {
+    /// Convert from an error returned by the capacity check or witness building.
+    pub fn from_anyhow(err: anyhow::Error) -> Self {
+        err.downcast::<Self>().unwrap_or_else(Self::TraceToWitness)
+    }
+
+    /// Utilization of the row limit, only for `CapacityOverflow`.
+    pub fn utilization(&self) -> Option<f64> {
+        match self {
+            Self::CapacityOverflow {
+                rows, row_limit, ..
+            } => Some(*rows as f64 / *row_limit as f64),
+            _ => None,
+        }
+    }
+
+    /// If the error could be gone on retry, i.e. an IO error while building the witness such as
+    /// the temp file exhaustion of `build_witness_spill_keccak`. Errors of the traces themselves
+    /// are never transient.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::TraceToWitness(e) => e.chain().any(|e| e.is::<std::io::Error>()),
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for WitnessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmptyChunk => write!(f, "Empty chunk trace"),
+            Self::TooManyBlocks {
+                num_block,
+                max_block,
+            } => write!(f, "too many blocks: {num_block} > {max_block}"),
+            Self::CapacityOverflow {
+                block_index,
+                rows,
+                row_limit,
+            } => write!(
+                f,
+                "chunk exceeds circuit capacity at block {block_index}: {rows} rows, overflow {} rows",
+                rows + 1 - row_limit
+            ),
+            Self::MemoryBudget {
+                budget,
+                num_block_fit,
+                num_block,
+            } => write!(
+                f,
+                "chunk exceeds memory budget {budget}: only {num_block_fit} of {num_block} blocks fit"
+            ),
+            Self::TraceToWitness(e) => write!(f, "failed to build witness: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for WitnessError {}
+
+/// What to do when the block traces exceed the circuit capacity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CapacityPolicy {
+    /// Truncate the trailing blocks which exceed the capacity.
+    #[default]
+    Trim,
+    /// Return an error, and keep the block traces as is.
+    Fail,
+}
+
 // FIXME: we need better API name for this.
 // This function also mutates the block trace.
 pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<()> {
+    check_batch_capacity_with_policy(block_traces, CapacityPolicy::Trim)
+}
+
+// Block traces are only mutated by `CapacityPolicy::Trim`.
+pub fn check_batch_capacity_with_policy(
+    block_traces: &mut Vec<BlockTrace>,
+    policy: CapacityPolicy,
+) -> Result<()> {
+    let report = batch_capacity_report(block_traces, policy)?;
+    block_traces.truncate(report.num_block_kept);
+    Ok(())
+}
+
+/// Result of the capacity check of a chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityReport {
+    /// Number of the leading blocks which fit in the circuit capacity.
+    pub num_block_kept: usize,
+    pub num_block_total: usize,
+    /// Rows of each sub-circuit of the kept blocks, in the order of `SUB_CIRCUIT_NAMES`. Empty if
+    /// the rows aren't checked.
+    pub rows: Vec<usize>,
+}
+
+impl CapacityReport {
+    pub fn is_trimmed(&self) -> bool {
+        self.num_block_kept < self.num_block_total
+    }
+}
+
+/// Same as `check_batch_capacity_with_policy`, but return the trimming decision instead of
+/// truncating the block traces.
+pub fn batch_capacity_report(
+    block_traces: &[BlockTrace],
+    policy: CapacityPolicy,
+) -> Result<CapacityReport> {
     let block_traces_len = block_traces.len();
     let total_tx_count = block_traces
         .iter()
@@ -70,19 +222,52 @@ pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<()> {
         total_tx_len_sum
     );
 
-    if block_traces_len > MAX_INNER_BLOCKS {
-        bail!("too many blocks");
+    let max_inner_blocks = CAPACITY_PROFILE.block_limit();
+    if block_traces_len > max_inner_blocks {
+        return Err(WitnessError::TooManyBlocks {
+            num_block: block_traces_len,
+            max_block: max_inner_blocks,
+        }
+        .into());
     }
 
-    if !*AUTO_TRUNCATE {
+    let mut report = CapacityReport {
+        num_block_kept: block_traces_len,
+        num_block_total: block_traces_len,
+        rows: vec![],
+    };
+    if policy == CapacityPolicy::Trim && !*AUTO_TRUNCATE {
         log::debug!("AUTO_TRUNCATE=false, keep batch as is");
-        return Ok(());
+        return Ok(report);
+    }
+
+    // Respect the memory budget if set, whichever is tighter with the row limit below.
+    if *MAX_WITNESS_MEMORY > 0 {
+        let max_blocks = max_blocks_for_memory(block_traces, *MAX_WITNESS_MEMORY);
+        if max_blocks < block_traces_len {
+            if policy == CapacityPolicy::Fail {
+                return Err(WitnessError::MemoryBudget {
+                    budget: *MAX_WITNESS_MEMORY,
+                    num_block_fit: max_blocks,
+                    num_block: block_traces_len,
+                }
+                .into());
+            }
+            log::warn!(
+                "truncate blocks [{}..{}) for memory budget {}",
+                max_blocks,
+                block_traces_len,
+                *MAX_WITNESS_MEMORY
+            );
+            report.num_block_kept = max_blocks;
+        }
     }
 
     let t = Instant::now();
+    let row_limits = CAPACITY_PROFILE.row_limits(*INNER_DEGREE);
+    let mut overflow = None;
     let mut acc = Vec::new();
-    let mut truncate_idx = block_traces.len();
-    for (idx, block) in block_traces.iter().enumerate() {
+    for (idx, block) in block_traces[..report.num_block_kept].iter().enumerate() {
         let usage = calculate_row_usage_of_trace(block)?;
         if acc.is_empty() {
             acc = usage;
@@ -103,23 +288,223 @@ pub fn check_batch_capacity(block_traces: &mut Vec<BlockTrace>) -> Result<()> {
             rows,
             rows_and_names
         );
-        if *rows >= (1 << *INNER_DEGREE) - 256 {
-            log::warn!("truncate blocks [{}..{})", idx, block_traces_len);
-            truncate_idx = idx;
+        if let Some((rows, row_limit)) = acc
+            .iter()
+            .zip(row_limits.iter())
+            .find(|(rows, row_limit)| rows >= row_limit)
+        {
+            let err = WitnessError::CapacityOverflow {
+                block_index: idx,
+                rows: *rows,
+                row_limit: *row_limit,
+            };
+            if policy == CapacityPolicy::Fail {
+                return Err(err.into());
+            }
+            log::warn!("truncate blocks [{}..{}), {}", idx, block_traces_len, err);
+            report.num_block_kept = idx;
+            overflow = Some(err);
             break;
         }
+        report.rows = acc.clone();
     }
     log::debug!("check_batch_capacity takes {:?}", t.elapsed());
-    block_traces.truncate(truncate_idx);
-    let total_tx_count2 = block_traces
+    let total_tx_count2 = block_traces[..report.num_block_kept]
         .iter()
         .map(|b| b.transactions.len())
         .sum::<usize>();
     if total_tx_count != 0 && total_tx_count2 == 0 {
         // the circuit cannot even prove the first non-empty block...
-        bail!("circuit capacity not enough");
+        return Err(overflow
+            .unwrap_or(WitnessError::MemoryBudget {
+                budget: *MAX_WITNESS_MEMORY,
+                num_block_fit: report.num_block_kept,
+                num_block: block_traces_len,
+            })
+            .into());
+    }
+    Ok(report)
+}
+
+/// Capacity check over a trace iterator with bounded memory, by folding the rows estimated from
+/// the trace proxies (by the estimator of the capacity profile) block by block. Return the report
+/// once the iterator is exhausted, or the error of the first bad trace.
+pub fn streaming_capacity(
+    traces: impl Iterator<Item = Result<BlockTrace>>,
+    degree: u32,
+) -> Result<CapacityReport> {
+    let row_limits = CAPACITY_PROFILE.row_limits(degree);
+    let max_inner_blocks = CAPACITY_PROFILE.block_limit();
+
+    let mut acc = vec![0; row_limits.len()];
+    let mut report = CapacityReport {
+        num_block_kept: 0,
+        num_block_total: 0,
+        rows: vec![],
+    };
+    let mut overflowed = false;
+    for trace in traces {
+        let trace = trace?;
+        report.num_block_total += 1;
+        if overflowed {
+            continue;
+        }
+
+        let rows = estimate_capacity(std::slice::from_ref(&trace), &CAPACITY_PROFILE.estimator);
+        acc.iter_mut()
+            .zip(rows)
+            .for_each(|(acc, rows)| *acc += rows);
+        if report.num_block_kept >= max_inner_blocks
+            || acc
+                .iter()
+                .zip(row_limits.iter())
+                .any(|(rows, limit)| rows >= limit)
+        {
+            log::debug!(
+                "streaming capacity overflows at block {:?}: {:?}",
+                trace.header.number,
+                acc
+            );
+            overflowed = true;
+        } else {
+            report.num_block_kept += 1;
+            report.rows = acc.clone();
+        }
+    }
+
+    log::info!("streaming capacity: {:?}", report);
+    Ok(report)
+}
+
+/// Signed difference of the estimated rows to the actual rows of each sub-circuit, e.g. to monitor
+/// the drift of the estimator. Sub-circuits missing in either report are skipped.
+pub fn capacity_estimate_error(
+    estimate: &CapacityReport,
+    actual: &CapacityReport,
+) -> BTreeMap<String, i64> {
+    SUB_CIRCUIT_NAMES
+        .iter()
+        .zip(estimate.rows.iter().zip(actual.rows.iter()))
+        .map(|(name, (estimate, actual))| (name.to_string(), *estimate as i64 - *actual as i64))
+        .collect()
+}
+
+/// Select the smallest of the degrees whose row limits (by the capacity profile) the chunk fits in.
+/// The rows are sized by the estimator of the capacity profile, without building the witness.
+/// Degrees are tried in ascending order, and error is returned if none fits.
+pub fn select_degree_for_capacity(block_traces: &[BlockTrace], degrees: &[u32]) -> Result<u32> {
+    let max_inner_blocks = CAPACITY_PROFILE.block_limit();
+    if block_traces.len() > max_inner_blocks {
+        return Err(WitnessError::TooManyBlocks {
+            num_block: block_traces.len(),
+            max_block: max_inner_blocks,
+        }
+        .into());
+    }
+    let rows = estimate_capacity(block_traces, &CAPACITY_PROFILE.estimator);
+
+    let mut degrees = degrees.to_vec();
+    degrees.sort_unstable();
+    degrees.dedup();
+    for degree in &degrees {
+        log::info!("try degree {degree}: estimated rows {rows:?}");
+        if CAPACITY_PROFILE.fits(&rows, *degree) {
+            log::info!("select degree {degree} for chunk of estimated rows {rows:?}");
+            return Ok(*degree);
+        }
+    }
+
+    bail!("chunk of estimated rows {rows:?} exceeds all degrees {degrees:?}")
+}
+
+/// Group the block traces into chunks which fit in the circuit capacity of degree (by the limits
+/// of the capacity profile), and build the witness block of each chunk. An error of a chunk is
+/// yielded as an `Err` item, and the following chunks are still built.
+pub fn witness_blocks_from_traces(
+    traces: impl Iterator<Item = BlockTrace>,
+    degree: u32,
+) -> impl Iterator<Item = Result<Block<Fr>>> {
+    TraceChunks {
+        traces,
+        degree,
+        pending: None,
+    }
+    .map(|chunk| chunk.and_then(|chunk| block_traces_to_witness_block(&chunk)))
+}
+
+struct TraceChunks<I> {
+    traces: I,
+    degree: u32,
+    // Trace (with its row usage) which didn't fit in the previous chunk.
+    pending: Option<(BlockTrace, Result<Vec<usize>>)>,
+}
+
+impl<I: Iterator<Item = BlockTrace>> Iterator for TraceChunks<I> {
+    type Item = Result<Vec<BlockTrace>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut chunk = vec![];
+        let mut acc: Vec<usize> = vec![];
+        loop {
+            let (trace, usage) = match self.pending.take() {
+                Some(pending) => pending,
+                None => match self.traces.next() {
+                    Some(trace) => {
+                        let usage = calculate_row_usage_of_trace(&trace);
+                        (trace, usage)
+                    }
+                    None => break,
+                },
+            };
+
+            let usage = match usage {
+                Ok(usage) => usage,
+                // Yield the current chunk first.
+                Err(e) if !chunk.is_empty() => {
+                    self.pending = Some((trace, Err(e)));
+                    break;
+                }
+                Err(e) => return Some(Err(e)),
+            };
+
+            let new_acc: Vec<usize> = if acc.is_empty() {
+                usage.clone()
+            } else {
+                acc.iter()
+                    .zip_eq(usage.iter())
+                    .map(|(a, u)| a + u)
+                    .collect()
+            };
+            let rows = new_acc.iter().copied().max().unwrap_or_default();
+            if !CAPACITY_PROFILE.fits(&new_acc, self.degree)
+                || chunk.len() >= CAPACITY_PROFILE.block_limit()
+            {
+                if chunk.is_empty() {
+                    return Some(Err(anyhow!(
+                        "block {:?} exceeds circuit capacity: {} rows",
+                        trace.header.number,
+                        rows
+                    )));
+                }
+                self.pending = Some((trace, Ok(usage)));
+                break;
+            }
+
+            log::debug!(
+                "add block {:?} to chunk, rows {}",
+                trace.header.number,
+                rows
+            );
+            acc = new_acc;
+            chunk.push(trace);
+        }
+
+        if chunk.is_empty() {
+            None
+        } else {
+            Some(Ok(chunk))
+        }
     }
-    Ok(())
 }
 
 pub fn update_state(
@@ -169,19 +554,93 @@ pub fn update_state(
     Ok(())
 }
 
+/// Options to skip building the witness of unused subcircuits.
+#[derive(Clone, Copy, Debug)]
+pub struct WitnessOptions {
+    /// Build the keccak inputs. If disabled, the keccak circuit is assigned as empty.
+    pub build_keccak: bool,
+    /// Build the MPT nodes and apply the MPT state. If disabled, it works like the light mode.
+    pub build_mpt: bool,
+}
+
+impl Default for WitnessOptions {
+    fn default() -> Self {
+        Self {
+            build_keccak: true,
+            build_mpt: true,
+        }
+    }
+}
+
+/// Sub-circuits to build the witness of, for partial proving in development. The evm and state
+/// circuits are always included, keccak and MPT could be skipped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CircuitSelection(u32);
+
+impl CircuitSelection {
+    pub const EVM: Self = Self(1);
+    pub const STATE: Self = Self(1 << 1);
+    pub const KECCAK: Self = Self(1 << 2);
+    pub const MPT: Self = Self(1 << 3);
+    pub const ALL: Self = Self(0b1111);
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_full(self) -> bool {
+        self.contains(Self::ALL)
+    }
+
+    pub fn witness_options(self) -> Result<WitnessOptions> {
+        if !self.contains(Self::EVM | Self::STATE) {
+            bail!("circuit selection {self:?} must include the evm and state circuits");
+        }
+
+        Ok(WitnessOptions {
+            build_keccak: self.contains(Self::KECCAK),
+            build_mpt: self.contains(Self::MPT),
+        })
+    }
+}
+
+impl std::ops::BitOr for CircuitSelection {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
 pub fn block_traces_to_witness_block(block_traces: &[BlockTrace]) -> Result<Block<Fr>> {
+    block_traces_to_witness_block_with_opts(block_traces, &WitnessOptions::default())
+}
+
+pub fn block_traces_to_witness_block_with_opts(
+    block_traces: &[BlockTrace],
+    opts: &WitnessOptions,
+) -> Result<Block<Fr>> {
     log::debug!(
-        "block_traces_to_witness_block, input len {:?}",
-        block_traces.len()
+        "block_traces_to_witness_block, input len {:?}, options {:?}",
+        block_traces.len(),
+        opts
     );
     let old_root = if block_traces.is_empty() {
         eth_types::Hash::zero()
     } else {
         block_traces[0].storage_trace.root_before
     };
+    let light_mode = !opts.build_mpt;
     let mut state = ZktrieState::construct(old_root);
-    update_state(&mut state, block_traces, false)?;
-    block_traces_to_witness_block_with_updated_state(block_traces, &mut state, false)
+    update_state(&mut state, block_traces, light_mode)?;
+    let mut witness_block =
+        block_traces_to_witness_block_with_updated_state(block_traces, &mut state, light_mode)?;
+
+    if !opts.build_keccak {
+        witness_block.keccak_inputs.clear();
+    }
+
+    Ok(witness_block)
 }
 
 pub fn block_traces_to_witness_block_with_updated_state(
@@ -285,6 +744,102 @@ pub fn block_traces_to_witness_block_with_updated_state(
     Ok(witness_block)
 }
 
+/// Scan the witness block for obviously invalid entries, so a corrupted trace could fail early
+/// with the offending location instead of an opaque assignment error during proving.
+pub fn sanity_check_witness(block: &Block<Fr>) -> Result<()> {
+    let mut last_rw_counter = 0;
+    for (tx_idx, tx) in block.txs.iter().enumerate() {
+        if tx.id != tx_idx + 1 {
+            bail!("invalid witness: tx {} has id {}", tx_idx, tx.id);
+        }
+        if tx.call_data_length != tx.call_data.len() {
+            bail!(
+                "invalid witness: tx {} has call_data_length {}, but call_data len {}",
+                tx_idx,
+                tx.call_data_length,
+                tx.call_data.len()
+            );
+        }
+        if !block.context.ctxs.contains_key(&tx.block_number) {
+            bail!(
+                "invalid witness: tx {} refers to unknown block {}",
+                tx_idx,
+                tx.block_number
+            );
+        }
+
+        for (step_idx, step) in tx.steps.iter().enumerate() {
+            if step.rw_counter < last_rw_counter {
+                bail!(
+                    "invalid witness: tx {} step {} has rw_counter {} less than previous {}",
+                    tx_idx,
+                    step_idx,
+                    step.rw_counter,
+                    last_rw_counter
+                );
+            }
+            last_rw_counter = step.rw_counter;
+
+            for (tag, idx) in &step.rw_indices {
+                let rws_len = block.rws.0.get(tag).map_or(0, Vec::len);
+                if *idx >= rws_len {
+                    bail!(
+                        "invalid witness: tx {} step {} refers to rw {:?}[{}], but only {} rows",
+                        tx_idx,
+                        step_idx,
+                        tag,
+                        idx,
+                        rws_len
+                    );
+                }
+            }
+        }
+    }
+
+    validate_keccak_inputs(block)
+}
+
+/// Check each tx hash recorded in the witness is the keccak of its signed RLP, and the signed RLP
+/// is one of the keccak inputs. Otherwise the keccak circuit would fail to look up the hash.
+/// Skipped if the keccak inputs aren't built.
+///
+/// It doesn't check the padding of the inputs, which is done by the keccak circuit itself.
+pub fn validate_keccak_inputs(block: &Block<Fr>) -> Result<()> {
+    if block.keccak_inputs.is_empty() {
+        return Ok(());
+    }
+
+    let hashes: HashSet<[u8; 32]> = block
+        .keccak_inputs
+        .iter()
+        .map(|input| keccak256(input))
+        .collect();
+    for (tx_idx, tx) in block.txs.iter().enumerate() {
+        if tx.rlp_signed.is_empty() {
+            continue;
+        }
+
+        let hash = keccak256(&tx.rlp_signed);
+        if hash != tx.hash.0 {
+            bail!(
+                "invalid witness: tx {} has hash {:?}, but keccak of signed rlp 0x{}",
+                tx_idx,
+                tx.hash,
+                hex::encode(hash)
+            );
+        }
+        if !hashes.contains(&hash) {
+            bail!(
+                "invalid witness: signed rlp of tx {} (len {}) not in keccak inputs",
+                tx_idx,
+                tx.rlp_signed.len()
+            );
+        }
+    }
+
+    Ok(())
+}
+
 pub fn decode_bytecode(bytecode: &str) -> Result<Vec<u8>> {
     let mut stripped = if let Some(stripped) = bytecode.strip_prefix("0x") {
         stripped.to_string()
@@ -0,0 +1,167 @@
+use super::{calculate_row_usage_of_witness_block, SUB_CIRCUIT_NAMES};
+use halo2_proofs::halo2curves::bn256::Fr;
+use std::fmt;
+use zkevm_circuits::evm_circuit::witness::Block;
+
+/// A first diverging row of a table, with the rows of both sides (None if beyond the end).
+#[derive(Debug, Clone)]
+pub struct RowDiff {
+    pub table: &'static str,
+    pub index: usize,
+    pub a: Option<String>,
+    pub b: Option<String>,
+}
+
+/// Differences of two witness blocks, e.g. built from the same trace by different versions.
+#[derive(Debug, Clone, Default)]
+pub struct WitnessDiff {
+    pub num_tx: (usize, usize),
+    pub num_step: (usize, usize),
+    pub num_rw: (usize, usize),
+    pub num_copy_event: (usize, usize),
+    pub num_keccak_input: (usize, usize),
+    // (name, rows of a, rows of b) of the sub-circuits with different row usage.
+    pub rows: Vec<(&'static str, usize, usize)>,
+    pub first_diverging_rows: Vec<RowDiff>,
+}
+
+impl WitnessDiff {
+    pub fn is_empty(&self) -> bool {
+        self.num_tx.0 == self.num_tx.1
+            && self.num_step.0 == self.num_step.1
+            && self.num_rw.0 == self.num_rw.1
+            && self.num_copy_event.0 == self.num_copy_event.1
+            && self.num_keccak_input.0 == self.num_keccak_input.1
+            && self.rows.is_empty()
+            && self.first_diverging_rows.is_empty()
+    }
+}
+
+impl fmt::Display for WitnessDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "witness blocks are identical");
+        }
+
+        let counts = [
+            ("txs", self.num_tx),
+            ("steps", self.num_step),
+            ("rws", self.num_rw),
+            ("copy events", self.num_copy_event),
+            ("keccak inputs", self.num_keccak_input),
+        ];
+        for (name, (a, b)) in counts {
+            if a != b {
+                writeln!(f, "- {name}: {a} -> {b}")?;
+            }
+        }
+        for (name, a, b) in &self.rows {
+            writeln!(f, "- {name} rows: {a} -> {b}")?;
+        }
+        for row in &self.first_diverging_rows {
+            writeln!(f, "- first diverging {} row {}:", row.table, row.index)?;
+            writeln!(f, "  - a: {}", row.a.as_deref().unwrap_or("<none>"))?;
+            writeln!(f, "  - b: {}", row.b.as_deref().unwrap_or("<none>"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare two witness blocks, and report the different counts and the first diverging row of the
+/// step, rw, copy and keccak tables.
+pub fn diff_witness_blocks(a: &Block<Fr>, b: &Block<Fr>) -> WitnessDiff {
+    let num_step = |block: &Block<Fr>| block.txs.iter().map(|tx| tx.steps.len()).sum::<usize>();
+    let num_rw = |block: &Block<Fr>| block.rws.0.values().map(Vec::len).sum::<usize>();
+
+    let rows = match (
+        calculate_row_usage_of_witness_block(a),
+        calculate_row_usage_of_witness_block(b),
+    ) {
+        (Ok(rows_a), Ok(rows_b)) => SUB_CIRCUIT_NAMES
+            .iter()
+            .zip(rows_a.into_iter().zip(rows_b))
+            .filter(|(_, (a, b))| a != b)
+            .map(|(name, (a, b))| (*name, a, b))
+            .collect(),
+        _ => {
+            log::warn!("failed to calculate row usage, skip the diff of rows");
+            vec![]
+        }
+    };
+
+    let steps = |block: &Block<Fr>| {
+        block
+            .txs
+            .iter()
+            .flat_map(|tx| {
+                tx.steps.iter().map(move |step| {
+                    format!(
+                        "tx {} {:?} rw_counter {} pc {}",
+                        tx.id, step.execution_state, step.rw_counter, step.program_counter
+                    )
+                })
+            })
+            .collect::<Vec<_>>()
+    };
+    let rws = |block: &Block<Fr>| {
+        block
+            .rws
+            .table_assignments()
+            .iter()
+            .map(|rw| format!("{rw:?}"))
+            .collect::<Vec<_>>()
+    };
+    let copy_events = |block: &Block<Fr>| {
+        block
+            .copy_events
+            .iter()
+            .map(|c| {
+                format!(
+                    "{:?} -> {:?}, {} bytes",
+                    c.src_type,
+                    c.dst_type,
+                    c.bytes.len()
+                )
+            })
+            .collect::<Vec<_>>()
+    };
+    let keccak_inputs = |block: &Block<Fr>| {
+        block
+            .keccak_inputs
+            .iter()
+            .map(|input| format!("0x{}", hex::encode(input)))
+            .collect::<Vec<_>>()
+    };
+
+    let first_diverging_rows = [
+        first_diverging_row("step", steps(a), steps(b)),
+        first_diverging_row("rw", rws(a), rws(b)),
+        first_diverging_row("copy", copy_events(a), copy_events(b)),
+        first_diverging_row("keccak", keccak_inputs(a), keccak_inputs(b)),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    WitnessDiff {
+        num_tx: (a.txs.len(), b.txs.len()),
+        num_step: (num_step(a), num_step(b)),
+        num_rw: (num_rw(a), num_rw(b)),
+        num_copy_event: (a.copy_events.len(), b.copy_events.len()),
+        num_keccak_input: (a.keccak_inputs.len(), b.keccak_inputs.len()),
+        rows,
+        first_diverging_rows,
+    }
+}
+
+fn first_diverging_row(table: &'static str, a: Vec<String>, b: Vec<String>) -> Option<RowDiff> {
+    let index = (0..a.len().max(b.len())).find(|i| a.get(*i) != b.get(*i))?;
+
+    Some(RowDiff {
+        table,
+        index,
+        a: a.get(index).cloned(),
+        b: b.get(index).cloned(),
+    })
+}
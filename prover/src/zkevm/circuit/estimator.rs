@@ -0,0 +1,172 @@
+use super::{calculate_row_usage_of_witness_block, SUB_CIRCUIT_NAMES};
+use anyhow::{bail, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use types::eth::BlockTrace;
+use zkevm_circuits::evm_circuit::witness::Block;
+
+// Proxies of the trace: num_block, num_tx, num_step, num_calldata_byte.
+const NUM_FEATURES: usize = 4;
+
+fn trace_features(block_traces: &[BlockTrace]) -> [f64; NUM_FEATURES] {
+    let num_tx: usize = block_traces.iter().map(|b| b.transactions.len()).sum();
+    let num_step: usize = block_traces
+        .iter()
+        .flat_map(|b| b.execution_results.iter())
+        .map(|r| r.exec_steps.len())
+        .sum();
+    let num_calldata_byte: usize = block_traces
+        .iter()
+        .flat_map(|b| b.transactions.iter())
+        .map(|t| t.data.len())
+        .sum();
+
+    [
+        block_traces.len() as f64,
+        num_tx as f64,
+        num_step as f64,
+        num_calldata_byte as f64,
+    ]
+}
+
+/// Linear coefficients from the trace proxies to the rows of each sub-circuit, in the order of
+/// `SUB_CIRCUIT_NAMES`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct EstimatorCoefficients {
+    pub coefficients: Vec<[f64; NUM_FEATURES]>,
+}
+
+impl Default for EstimatorCoefficients {
+    // Conservative guess: rows grow with steps and calldata only.
+    fn default() -> Self {
+        Self {
+            coefficients: vec![[0.0, 0.0, 32.0, 8.0]; SUB_CIRCUIT_NAMES.len()],
+        }
+    }
+}
+
+impl EstimatorCoefficients {
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let coefficients: Self = serde_json::from_slice(&fs::read(path)?)?;
+        if coefficients.coefficients.len() != SUB_CIRCUIT_NAMES.len() {
+            bail!(
+                "estimator coefficients of {} sub-circuits, expected {}",
+                coefficients.coefficients.len(),
+                SUB_CIRCUIT_NAMES.len()
+            );
+        }
+
+        Ok(coefficients)
+    }
+
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Estimate the rows of each sub-circuit from the trace proxies, without building the witness.
+pub fn estimate_capacity(
+    block_traces: &[BlockTrace],
+    coefficients: &EstimatorCoefficients,
+) -> Vec<usize> {
+    let features = trace_features(block_traces);
+    coefficients
+        .coefficients
+        .iter()
+        .map(|c| {
+            let rows: f64 = c.iter().zip(features.iter()).map(|(c, f)| c * f).sum();
+            rows.max(0.0).ceil() as usize
+        })
+        .collect()
+}
+
+/// Fit the coefficients by least squares from sample builds of chunk traces and their witness
+/// blocks.
+pub fn calibrate_estimator(
+    samples: &[(Vec<BlockTrace>, Block<Fr>)],
+) -> Result<EstimatorCoefficients> {
+    if samples.is_empty() {
+        bail!("no samples to calibrate the estimator");
+    }
+
+    let features: Vec<_> = samples
+        .iter()
+        .map(|(traces, _)| trace_features(traces))
+        .collect();
+    let rows = samples
+        .iter()
+        .map(|(_, block)| calculate_row_usage_of_witness_block(block))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Normal equations (X^T X) c = X^T y, shared by all sub-circuits.
+    let mut xtx = [[0.0; NUM_FEATURES]; NUM_FEATURES];
+    for x in &features {
+        for (row, xi) in xtx.iter_mut().zip(x.iter()) {
+            for (cell, xj) in row.iter_mut().zip(x.iter()) {
+                *cell += xi * xj;
+            }
+        }
+    }
+    // Tiny ridge term to keep it solvable if a proxy is constant among samples.
+    for (i, row) in xtx.iter_mut().enumerate() {
+        row[i] += 1e-6 * (1.0 + row[i]);
+    }
+
+    let coefficients = (0..SUB_CIRCUIT_NAMES.len())
+        .map(|circuit_idx| {
+            let mut xty = [0.0; NUM_FEATURES];
+            for (x, rows) in features.iter().zip(rows.iter()) {
+                for (y, xi) in xty.iter_mut().zip(x.iter()) {
+                    *y += xi * rows[circuit_idx] as f64;
+                }
+            }
+            solve(xtx, xty)
+        })
+        .collect();
+
+    let coefficients = EstimatorCoefficients { coefficients };
+    log::info!(
+        "calibrated estimator by {} samples: {:?}",
+        samples.len(),
+        coefficients
+    );
+    Ok(coefficients)
+}
+
+// Solve the linear system by Gaussian elimination with partial pivoting.
+fn solve(
+    mut a: [[f64; NUM_FEATURES]; NUM_FEATURES],
+    mut b: [f64; NUM_FEATURES],
+) -> [f64; NUM_FEATURES] {
+    for col in 0..NUM_FEATURES {
+        let pivot = (col..NUM_FEATURES)
+            .max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))
+            .unwrap();
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        let (pivot_row, pivot_b) = (a[col], b[col]);
+        if pivot_row[col].abs() < f64::EPSILON {
+            continue;
+        }
+        for (row, b_row) in a.iter_mut().zip(b.iter_mut()).skip(col + 1) {
+            let factor = row[col] / pivot_row[col];
+            for (cell, p) in row.iter_mut().zip(pivot_row.iter()).skip(col) {
+                *cell -= factor * p;
+            }
+            *b_row -= factor * pivot_b;
+        }
+    }
+
+    let mut x = [0.0; NUM_FEATURES];
+    for row in (0..NUM_FEATURES).rev() {
+        if a[row][row].abs() < f64::EPSILON {
+            continue;
+        }
+        let sum: f64 = (row + 1..NUM_FEATURES).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    x
+}
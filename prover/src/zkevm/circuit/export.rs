@@ -0,0 +1,217 @@
+use anyhow::Result;
+use halo2_proofs::halo2curves::bn256::Fr;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+use zkevm_circuits::evm_circuit::witness::Block;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    /// Requires feature `export-parquet`.
+    Parquet,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+}
+
+// A table of string cells, numbers in decimal and words in 0x-prefixed hex.
+struct Table {
+    name: &'static str,
+    columns: &'static [&'static str],
+    rows: Vec<Vec<String>>,
+}
+
+/// Write the step, rw, copy and keccak tables of the witness block to `{out_dir}/{table}.{ext}`,
+/// e.g. to study the opcode distributions and capacity drivers by SQL.
+pub fn export_witness_tables(block: &Block<Fr>, out_dir: &str, format: ExportFormat) -> Result<()> {
+    for table in witness_tables(block) {
+        let path = Path::new(out_dir).join(format!("{}.{}", table.name, format.extension()));
+        match format {
+            ExportFormat::Csv => write_csv(&table, &path)?,
+            ExportFormat::Parquet => write_parquet(&table, &path)?,
+        }
+        log::info!("exported {} rows to {}", table.rows.len(), path.display());
+    }
+
+    Ok(())
+}
+
+fn witness_tables(block: &Block<Fr>) -> Vec<Table> {
+    let steps = block
+        .txs
+        .iter()
+        .flat_map(|tx| {
+            tx.steps.iter().map(move |step| {
+                vec![
+                    tx.id.to_string(),
+                    format!("{:?}", step.execution_state),
+                    step.opcode.map(|op| format!("{op:?}")).unwrap_or_default(),
+                    step.rw_counter.to_string(),
+                    step.program_counter.to_string(),
+                    step.stack_pointer.to_string(),
+                    step.gas_left.to_string(),
+                    step.gas_cost.to_string(),
+                ]
+            })
+        })
+        .collect();
+
+    let rws = block
+        .rws
+        .table_assignments()
+        .iter()
+        .map(|rw| {
+            vec![
+                rw.rw_counter().to_string(),
+                rw.is_write().to_string(),
+                format!("{:?}", rw.tag()),
+                rw.id().map(|id| id.to_string()).unwrap_or_default(),
+                rw.address()
+                    .map(|address| format!("{address:?}"))
+                    .unwrap_or_default(),
+                rw.field_tag()
+                    .map(|tag| tag.to_string())
+                    .unwrap_or_default(),
+                rw.storage_key()
+                    .map(|key| format!("{key:#x}"))
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let copy_events = block
+        .copy_events
+        .iter()
+        .map(|c| {
+            vec![
+                format!("{:?}", c.src_type),
+                format!("{:?}", c.dst_type),
+                c.src_addr.to_string(),
+                c.dst_addr.to_string(),
+                c.bytes.len().to_string(),
+                c.rw_counter_start.0.to_string(),
+            ]
+        })
+        .collect();
+
+    let keccak_inputs = block
+        .keccak_inputs
+        .iter()
+        .map(|input| vec![input.len().to_string(), format!("0x{}", hex::encode(input))])
+        .collect();
+
+    vec![
+        Table {
+            name: "steps",
+            columns: &[
+                "tx_id",
+                "execution_state",
+                "opcode",
+                "rw_counter",
+                "program_counter",
+                "stack_pointer",
+                "gas_left",
+                "gas_cost",
+            ],
+            rows: steps,
+        },
+        Table {
+            name: "rws",
+            columns: &[
+                "rw_counter",
+                "is_write",
+                "tag",
+                "id",
+                "address",
+                "field_tag",
+                "storage_key",
+            ],
+            rows: rws,
+        },
+        Table {
+            name: "copy_events",
+            columns: &[
+                "src_type",
+                "dst_type",
+                "src_addr",
+                "dst_addr",
+                "len",
+                "rw_counter_start",
+            ],
+            rows: copy_events,
+        },
+        Table {
+            name: "keccak_inputs",
+            columns: &["len", "input"],
+            rows: keccak_inputs,
+        },
+    ]
+}
+
+fn write_csv(table: &Table, path: &Path) -> Result<()> {
+    let escape = |cell: &str| {
+        if cell.contains([',', '"', '\n']) {
+            format!("\"{}\"", cell.replace('"', "\"\""))
+        } else {
+            cell.to_string()
+        }
+    };
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{}", table.columns.join(","))?;
+    for row in &table.rows {
+        let row: Vec<_> = row.iter().map(|cell| escape(cell)).collect();
+        writeln!(writer, "{}", row.join(","))?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+#[cfg(feature = "export-parquet")]
+fn write_parquet(table: &Table, path: &Path) -> Result<()> {
+    use arrow::{
+        array::{ArrayRef, StringArray},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let fields: Vec<_> = table
+        .columns
+        .iter()
+        .map(|column| Field::new(*column, DataType::Utf8, false))
+        .collect();
+    let schema = Arc::new(Schema::new(fields));
+    let arrays = (0..table.columns.len())
+        .map(|i| {
+            let values = table.rows.iter().map(|row| row[i].as_str());
+            Arc::new(StringArray::from_iter_values(values)) as ArrayRef
+        })
+        .collect();
+    let batch = RecordBatch::try_new(schema.clone(), arrays)?;
+
+    let mut writer = ArrowWriter::try_new(File::create(path)?, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "export-parquet"))]
+fn write_parquet(_table: &Table, path: &Path) -> Result<()> {
+    anyhow::bail!(
+        "cannot export {}, feature `export-parquet` is disabled",
+        path.display()
+    )
+}
@@ -0,0 +1,164 @@
+use super::{block_traces_to_witness_block, WITNESS_MEMORY};
+use anyhow::{bail, Result};
+use serde_derive::{Deserialize, Serialize};
+use std::{fs, path::Path};
+use types::eth::BlockTrace;
+
+/// Memory cost (in bytes) of witness building per item of a block trace.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct WitnessMemoryCoefficients {
+    pub per_block: usize,
+    pub per_tx: usize,
+    pub per_step: usize,
+    pub per_calldata_byte: usize,
+    pub per_proof_node: usize,
+}
+
+impl Default for WitnessMemoryCoefficients {
+    // Rough and conservative guess, not measured. Calibrate it by `calibrate_witness_memory` on
+    // the worker, and load it by env `WITNESS_MEMORY_COEFFICIENTS`.
+    fn default() -> Self {
+        Self {
+            per_block: 4 * 1024 * 1024,
+            per_tx: 256 * 1024,
+            per_step: 8 * 1024,
+            per_calldata_byte: 512,
+            per_proof_node: 2 * 1024,
+        }
+    }
+}
+
+impl WitnessMemoryCoefficients {
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn dump<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)?;
+        Ok(())
+    }
+
+    fn scaled(&self, scale: f64) -> Self {
+        let scale = |bytes: usize| (bytes as f64 * scale).ceil() as usize;
+        Self {
+            per_block: scale(self.per_block),
+            per_tx: scale(self.per_tx),
+            per_step: scale(self.per_step),
+            per_calldata_byte: scale(self.per_calldata_byte),
+            per_proof_node: scale(self.per_proof_node),
+        }
+    }
+}
+
+/// Estimate the peak memory of witness building for a block trace, from the counts of tx, step,
+/// calldata and storage proof, by the coefficients of env `WITNESS_MEMORY_COEFFICIENTS`.
+pub fn estimated_witness_bytes(block_trace: &BlockTrace) -> usize {
+    estimated_witness_bytes_with(block_trace, &WITNESS_MEMORY)
+}
+
+pub fn estimated_witness_bytes_with(
+    block_trace: &BlockTrace,
+    coefficients: &WitnessMemoryCoefficients,
+) -> usize {
+    let num_step = block_trace
+        .execution_results
+        .iter()
+        .map(|r| r.exec_steps.len())
+        .sum::<usize>();
+    let calldata_len = block_trace
+        .transactions
+        .iter()
+        .map(|t| t.data.len())
+        .sum::<usize>();
+    let storage_trace = &block_trace.storage_trace;
+    let num_proof_node = storage_trace
+        .proofs
+        .iter()
+        .flat_map(|p| p.values())
+        .chain(
+            storage_trace
+                .storage_proofs
+                .values()
+                .flat_map(|p| p.values()),
+        )
+        .map(Vec::len)
+        .sum::<usize>()
+        + storage_trace.deletion_proofs.len();
+
+    coefficients.per_block
+        + block_trace.transactions.len() * coefficients.per_tx
+        + num_step * coefficients.per_step
+        + calldata_len * coefficients.per_calldata_byte
+        + num_proof_node * coefficients.per_proof_node
+}
+
+/// Return how many leading blocks could fit under the memory budget of witness building.
+pub fn max_blocks_for_memory(block_traces: &[BlockTrace], available_bytes: usize) -> usize {
+    let mut acc = 0;
+    for (idx, block_trace) in block_traces.iter().enumerate() {
+        acc += estimated_witness_bytes(block_trace);
+        if acc > available_bytes {
+            return idx;
+        }
+    }
+
+    block_traces.len()
+}
+
+/// Calibrate the coefficients by sample builds of chunk traces on this machine. The peak resident
+/// memory of each build is measured, and the default coefficients are scaled by the largest
+/// ratio of the measured to the estimated, so none of the samples is underestimated.
+/// Only supported on linux, since the peak is reset and read by procfs.
+pub fn calibrate_witness_memory(samples: &[Vec<BlockTrace>]) -> Result<WitnessMemoryCoefficients> {
+    if samples.is_empty() {
+        bail!("no samples to calibrate the witness memory");
+    }
+
+    let default = WitnessMemoryCoefficients::default();
+    let mut scale: f64 = 0.0;
+    for block_traces in samples {
+        if block_traces.is_empty() {
+            bail!("empty sample to calibrate the witness memory");
+        }
+        let measured = measure_peak_bytes(|| {
+            block_traces_to_witness_block(block_traces)?;
+            Ok(())
+        })?;
+        let estimated: usize = block_traces
+            .iter()
+            .map(|trace| estimated_witness_bytes_with(trace, &default))
+            .sum();
+        log::debug!(
+            "witness memory of {} blocks: measured {measured}, estimated {estimated}",
+            block_traces.len()
+        );
+        scale = scale.max(measured as f64 / estimated as f64);
+    }
+
+    let coefficients = default.scaled(scale);
+    log::info!(
+        "calibrated witness memory by {} samples, scale {scale:.3}: {:?}",
+        samples.len(),
+        coefficients
+    );
+    Ok(coefficients)
+}
+
+// Growth of the peak resident memory over the resident memory before `f`.
+#[cfg(target_os = "linux")]
+fn measure_peak_bytes(f: impl FnOnce() -> Result<()>) -> Result<usize> {
+    let process = procfs::process::Process::myself()?;
+    // Writing 5 to `clear_refs` resets the peak resident memory to the current one.
+    fs::write("/proc/self/clear_refs", "5")?;
+    let rss_kb = process.status()?.vmrss.unwrap_or_default();
+
+    f()?;
+
+    let peak_kb = process.status()?.vmhwm.unwrap_or_default();
+    Ok(peak_kb.saturating_sub(rss_kb) as usize * 1024)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn measure_peak_bytes(_f: impl FnOnce() -> Result<()>) -> Result<usize> {
+    bail!("witness memory calibration is only supported on linux")
+}
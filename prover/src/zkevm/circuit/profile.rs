@@ -0,0 +1,113 @@
+use super::{EstimatorCoefficients, MAX_INNER_BLOCKS, SUB_CIRCUIT_NAMES};
+use anyhow::{bail, Result};
+
+/// Capacity limits and estimator coefficients tuned for the opcode distribution of a chain.
+#[derive(Debug, Clone)]
+pub struct CapacityProfile {
+    pub name: &'static str,
+    /// Max number of blocks in a chunk, no more than the circuit `MAX_INNER_BLOCKS`.
+    pub max_inner_blocks: usize,
+    /// Ratio of the degree row limit usable by each sub-circuit, in the order of
+    /// `SUB_CIRCUIT_NAMES`.
+    pub row_limit_ratios: [f64; SUB_CIRCUIT_NAMES.len()],
+    pub estimator: EstimatorCoefficients,
+}
+
+impl Default for CapacityProfile {
+    fn default() -> Self {
+        Self::conservative()
+    }
+}
+
+impl CapacityProfile {
+    /// 90% of the row limit for every sub-circuit, with the default estimator, as a margin for
+    /// the estimate error on any chain.
+    pub fn conservative() -> Self {
+        Self {
+            name: "conservative",
+            max_inner_blocks: MAX_INNER_BLOCKS,
+            row_limit_ratios: [0.9; SUB_CIRCUIT_NAMES.len()],
+            estimator: EstimatorCoefficients::default(),
+        }
+    }
+
+    /// Mainnet blocks are keccak and storage heavy, leave a margin for keccak and mpt circuits.
+    ///
+    /// Notice the numbers of the chain profiles are hand-picked guesses, not fitted from chain
+    /// data. Fit them by `calibrate_estimator` on sampled chunks of the chain before relying on
+    /// them.
+    pub fn mainnet() -> Self {
+        let mut row_limit_ratios = [1.0; SUB_CIRCUIT_NAMES.len()];
+        set_ratio(&mut row_limit_ratios, "keccak", 0.95);
+        set_ratio(&mut row_limit_ratios, "mpt", 0.95);
+
+        Self {
+            name: "mainnet",
+            max_inner_blocks: MAX_INNER_BLOCKS,
+            row_limit_ratios,
+            estimator: EstimatorCoefficients {
+                coefficients: vec![[0.0, 0.0, 36.0, 8.0]; SUB_CIRCUIT_NAMES.len()],
+            },
+        }
+    }
+
+    /// Sepolia has many small blocks of simple transfers, so the per block and per tx rows
+    /// dominate. The numbers are hand-picked like the ones of `mainnet`.
+    pub fn sepolia() -> Self {
+        Self {
+            name: "sepolia",
+            max_inner_blocks: MAX_INNER_BLOCKS,
+            row_limit_ratios: [1.0; SUB_CIRCUIT_NAMES.len()],
+            estimator: EstimatorCoefficients {
+                coefficients: vec![[64.0, 256.0, 24.0, 8.0]; SUB_CIRCUIT_NAMES.len()],
+            },
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "conservative" => Ok(Self::conservative()),
+            "mainnet" => Ok(Self::mainnet()),
+            "sepolia" => Ok(Self::sepolia()),
+            _ => bail!("unknown capacity profile {name}"),
+        }
+    }
+
+    /// Row limit of each sub-circuit for the degree.
+    pub fn row_limits(&self, degree: u32) -> Vec<usize> {
+        let row_limit = (1usize << degree).saturating_sub(256);
+        self.row_limit_ratios
+            .iter()
+            .map(|ratio| (row_limit as f64 * ratio) as usize)
+            .collect()
+    }
+
+    /// Whether the rows of each sub-circuit are all under the row limits of the degree.
+    pub fn fits(&self, rows: &[usize], degree: u32) -> bool {
+        rows.iter()
+            .zip(self.row_limits(degree))
+            .all(|(rows, row_limit)| *rows < row_limit)
+    }
+
+    /// Min degree whose row limits cover the rows of each sub-circuit.
+    pub fn min_degree(&self, rows: &[usize]) -> u32 {
+        let mut degree = 1;
+        while !self.fits(rows, degree) {
+            degree += 1;
+        }
+        degree
+    }
+
+    /// Max number of blocks in a chunk, capped by the circuit `MAX_INNER_BLOCKS`.
+    pub fn block_limit(&self) -> usize {
+        self.max_inner_blocks.min(MAX_INNER_BLOCKS)
+    }
+}
+
+fn set_ratio(ratios: &mut [f64], sub_circuit: &str, ratio: f64) {
+    let idx = SUB_CIRCUIT_NAMES
+        .iter()
+        .position(|name| *name == sub_circuit)
+        .unwrap();
+    ratios[idx] = ratio;
+}
@@ -0,0 +1,125 @@
+use super::block_traces_to_witness_block;
+use crate::config::WITNESS_SPILL_DIR;
+use anyhow::{bail, Result};
+use halo2_proofs::halo2curves::bn256::Fr;
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+    path::PathBuf,
+};
+use types::eth::BlockTrace;
+use zkevm_circuits::evm_circuit::witness::Block;
+
+/// A witness block with the keccak inputs spilled to a temp file, which is removed on drop.
+#[derive(Debug)]
+pub struct SpilledWitness {
+    block: Block<Fr>,
+    path: PathBuf,
+    num_keccak_input: usize,
+}
+
+impl SpilledWitness {
+    /// The witness block without keccak inputs.
+    pub fn block(&self) -> &Block<Fr> {
+        &self.block
+    }
+
+    pub fn num_keccak_input(&self) -> usize {
+        self.num_keccak_input
+    }
+
+    /// Iterate the spilled keccak inputs from disk.
+    pub fn keccak_inputs(&self) -> Result<KeccakInputs> {
+        Ok(KeccakInputs {
+            reader: BufReader::new(File::open(&self.path)?),
+            remaining: self.num_keccak_input,
+        })
+    }
+
+    /// Read back the keccak inputs, and return the full witness block for proving.
+    pub fn into_block(mut self) -> Result<Block<Fr>> {
+        let keccak_inputs = self.keccak_inputs()?.collect::<Result<Vec<_>>>()?;
+        let mut block = std::mem::take(&mut self.block);
+        block.keccak_inputs = keccak_inputs;
+
+        Ok(block)
+    }
+}
+
+impl Drop for SpilledWitness {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            log::warn!(
+                "failed to remove witness spill {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Row iterator of the spilled keccak inputs.
+pub struct KeccakInputs {
+    reader: BufReader<File>,
+    remaining: usize,
+}
+
+impl Iterator for KeccakInputs {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+
+        let mut read_input = || -> Result<Vec<u8>> {
+            let mut len = [0u8; 8];
+            self.reader.read_exact(&mut len)?;
+            let mut input = vec![0u8; u64::from_le_bytes(len) as usize];
+            self.reader.read_exact(&mut input)?;
+            Ok(input)
+        };
+        Some(read_input())
+    }
+}
+
+/// Build the witness block like `block_traces_to_witness_block`, then spill its keccak inputs to a
+/// temp file in `WITNESS_SPILL_DIR`, so they're not kept in memory while the witness waits to be
+/// proved.
+///
+/// Notice that it doesn't bound the peak memory: the block is fully built in memory first, and the
+/// rw table stays there, since the witness generation of zkevm-circuits has no streaming output.
+pub fn build_witness_spill_keccak(block_traces: &[BlockTrace]) -> Result<SpilledWitness> {
+    let mut block = block_traces_to_witness_block(block_traces)?;
+
+    let dir = PathBuf::from(&*WITNESS_SPILL_DIR);
+    if !dir.is_dir() {
+        bail!("witness spill dir {} not exists", dir.display());
+    }
+    let path = dir.join(format!(
+        "witness_{}_{}.spill",
+        std::process::id(),
+        block.context.first_or_default().number
+    ));
+
+    let keccak_inputs = std::mem::take(&mut block.keccak_inputs);
+    let mut writer = BufWriter::new(File::create(&path)?);
+    for input in &keccak_inputs {
+        writer.write_all(&(input.len() as u64).to_le_bytes())?;
+        writer.write_all(input)?;
+    }
+    writer.flush()?;
+
+    let num_bytes: usize = keccak_inputs.iter().map(Vec::len).sum();
+    log::info!(
+        "spilled {} keccak inputs of {num_bytes} bytes to {}",
+        keccak_inputs.len(),
+        path.display()
+    );
+
+    Ok(SpilledWitness {
+        block,
+        path,
+        num_keccak_input: keccak_inputs.len(),
+    })
+}
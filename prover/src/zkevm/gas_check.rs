@@ -0,0 +1,120 @@
+use anyhow::{bail, Result};
+use eth_types::evm_types::OpcodeId;
+use types::eth::{BlockTrace, ExecStep};
+
+#[derive(Debug, Clone)]
+pub struct GasMismatch {
+    pub tx_index: usize,
+    // None if the mismatch is of the whole tx.
+    pub step_index: Option<usize>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GasCheckOutcome {
+    pub num_tx: usize,
+    pub num_step: usize,
+    pub mismatches: Vec<GasMismatch>,
+}
+
+impl GasCheckOutcome {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Cross-check the gas accounting and results of the struct logs of a block trace, so a malformed
+/// trace could be caught before proving.
+/// It doesn't re-execute EVM, and only checks the trace is self-consistent, so a wrong post-state
+/// isn't caught.
+pub fn check_trace_gas_consistency(trace: &BlockTrace) -> Result<GasCheckOutcome> {
+    if trace.transactions.len() != trace.execution_results.len() {
+        bail!(
+            "invalid trace: {} transactions, but {} execution results",
+            trace.transactions.len(),
+            trace.execution_results.len()
+        );
+    }
+
+    let mut outcome = GasCheckOutcome {
+        num_tx: trace.transactions.len(),
+        ..Default::default()
+    };
+    for (tx_index, (tx, result)) in trace
+        .transactions
+        .iter()
+        .zip(trace.execution_results.iter())
+        .enumerate()
+    {
+        outcome.num_step += result.exec_steps.len();
+
+        if result.gas > tx.gas {
+            outcome.mismatches.push(GasMismatch {
+                tx_index,
+                step_index: None,
+                reason: format!("gas used {} exceeds gas limit {}", result.gas, tx.gas),
+            });
+        }
+
+        for (step_index, (step, next)) in result
+            .exec_steps
+            .iter()
+            .zip(result.exec_steps.iter().skip(1))
+            .enumerate()
+        {
+            if let Some(reason) = check_step_gas(step, next) {
+                outcome.mismatches.push(GasMismatch {
+                    tx_index,
+                    step_index: Some(step_index),
+                    reason,
+                });
+            }
+        }
+
+        if let Some(last) = result.exec_steps.last() {
+            if last.depth == 1 && last.error.is_some() && !result.failed {
+                outcome.mismatches.push(GasMismatch {
+                    tx_index,
+                    step_index: Some(result.exec_steps.len() - 1),
+                    reason: format!("tx ends with error {:?}, but not failed", last.error),
+                });
+            }
+        }
+    }
+
+    log::debug!(
+        "gas check of block trace {:?}: {} tx, {} steps, {} mismatches",
+        trace.header.number,
+        outcome.num_tx,
+        outcome.num_step,
+        outcome.mismatches.len()
+    );
+    Ok(outcome)
+}
+
+// Gas left of the next step must be `gas - gas_cost` in the same call context. Skip the calls
+// and creates, since the gas of callee is returned to caller.
+fn check_step_gas(step: &ExecStep, next: &ExecStep) -> Option<String> {
+    if step.depth != next.depth || step.error.is_some() {
+        return None;
+    }
+    if matches!(
+        step.op,
+        OpcodeId::CALL
+            | OpcodeId::CALLCODE
+            | OpcodeId::DELEGATECALL
+            | OpcodeId::STATICCALL
+            | OpcodeId::CREATE
+            | OpcodeId::CREATE2
+    ) {
+        return None;
+    }
+
+    match step.gas.checked_sub(step.gas_cost) {
+        Some(gas_left) if gas_left == next.gas => None,
+        _ => Some(format!(
+            "{:?} at pc {} with gas {} and gas cost {}, but next gas {}",
+            step.op, step.pc, step.gas, step.gas_cost, next.gas
+        )),
+    }
+}
@@ -1,21 +1,33 @@
 use super::circuit::{
-    block_traces_to_witness_block, check_batch_capacity, SuperCircuit, TargetCircuit,
+    batch_capacity_report, block_traces_to_witness_block_with_opts,
+    calculate_row_usage_of_witness_block, capacity_estimate_error, capacity_profile,
+    select_degree_for_capacity, streaming_capacity, CapacityPolicy, SuperCircuit, TargetCircuit,
+    WitnessOptions,
 };
 use crate::{
     config::{CHUNK_DEGREE, INNER_DEGREE},
-    utils::{load_params, metric_of_witness_block, read_env_var, tick},
+    proof::{ProofMetadata, ProofStats},
+    transcript::try_dump_evm_transcript,
+    utils::{
+        assert_aggregation_params, filter_block_trace, gen_rng, load_params,
+        metric_of_witness_block, phase_span, read_env_flag, read_env_var, tick,
+    },
     Proof,
 };
 use anyhow::{bail, Result};
+use eth_types::H256;
 use halo2_proofs::poly::{
     commitment::{Params, ParamsProver},
     kzg::commitment::ParamsVerifierKZG,
 };
 use log::info;
 use once_cell::sync::Lazy;
-use rand::SeedableRng;
-use rand_xorshift::XorShiftRng;
-use std::collections::HashMap;
+use rand::Rng;
+use std::{
+    collections::{BTreeMap, HashMap},
+    iter,
+    time::Instant,
+};
 use types::eth::BlockTrace;
 
 use halo2_proofs::{
@@ -25,11 +37,14 @@ use halo2_proofs::{
     poly::kzg::commitment::ParamsKZG,
 };
 use snark_verifier_sdk::{
-    gen_evm_proof_shplonk, gen_pk, gen_snark_shplonk, AggregationCircuit, CircuitExt, Snark,
+    gen_evm_proof_shplonk, gen_pk, gen_snark_shplonk, verify_snark_shplonk, AggregationCircuit,
+    CircuitExt, Snark,
 };
 
+mod batch;
 mod evm;
 mod mock;
+mod partial;
 
 #[cfg(target_os = "linux")]
 extern crate procfs;
@@ -37,6 +52,9 @@ extern crate procfs;
 #[allow(dead_code)]
 pub static OPT_MEM: Lazy<bool> = Lazy::new(|| read_env_var("OPT_MEM", false));
 pub static MOCK_PROVE: Lazy<bool> = Lazy::new(|| read_env_var("MOCK_PROVE", false));
+// Generate a throwaway proof in `from_params_dir` by `WARMUP=1`, so the first real proof isn't
+// slowed by cold caches.
+static WARMUP: Lazy<bool> = Lazy::new(|| read_env_flag("WARMUP"));
 
 #[derive(Debug)]
 // This is the aggregation prover that takes in a list of traces, produces
@@ -48,6 +66,13 @@ pub struct Prover {
     /// Those keys are stored as a hash map, and keyed by a `name` String.
     pub inner_pks: HashMap<String, ProvingKey<G1Affine>>,
     pub chunk_pk: Option<ProvingKey<G1Affine>>,
+    /// Params of the degree fallback, tried in ascending order if a chunk overflows
+    /// `INNER_DEGREE`. They are downsized from `chunk_params`.
+    pub fallback_params: BTreeMap<u32, ParamsKZG<Bn256>>,
+    /// Pks of the aggregation circuits other than `chunk_pk`, e.g. of batches of several chunks
+    /// or of snarks of a fallback degree. They are keyed by the inner degrees of the aggregated
+    /// snarks, so by the number of snarks as well.
+    pub agg_pks: HashMap<Vec<u32>, ProvingKey<G1Affine>>,
 }
 
 impl Prover {
@@ -80,6 +105,8 @@ impl Prover {
             chunk_params,
             inner_pks: Default::default(),
             chunk_pk: None,
+            fallback_params: Default::default(),
+            agg_pks: Default::default(),
         }
     }
 
@@ -97,7 +124,57 @@ impl Prover {
             new_params
         });
 
-        Self::from_params(inner_params, chunk_params)
+        let mut prover = Self::from_params(inner_params, chunk_params);
+        if *WARMUP {
+            prover.warm_up();
+        }
+
+        prover
+    }
+
+    /// Set the degree fallback. If a chunk overflows `INNER_DEGREE`, the smallest of these degrees
+    /// which fits the chunk by the capacity estimate is used, instead of trimming the chunk.
+    /// The params are downsized from `chunk_params`, so degrees above `CHUNK_DEGREE` are rejected.
+    pub fn set_degree_fallback(&mut self, degree_fallback: &[u32]) -> Result<()> {
+        let mut fallback_params = BTreeMap::new();
+        for degree in degree_fallback {
+            if *degree <= *INNER_DEGREE {
+                log::warn!("skip fallback degree {degree}, not above inner degree");
+                continue;
+            }
+            if *degree > self.chunk_params.k() {
+                bail!(
+                    "no params of fallback degree {degree}, max degree {}",
+                    self.chunk_params.k()
+                );
+            }
+
+            let mut params = self.chunk_params.clone();
+            params.downsize(*degree);
+            fallback_params.insert(*degree, params);
+        }
+
+        log::info!(
+            "degree fallback: {:?}",
+            fallback_params.keys().collect::<Vec<_>>()
+        );
+        self.fallback_params = fallback_params;
+        Ok(())
+    }
+
+    /// Pk of the aggregation of snarks of the inner degrees. `chunk_pk` is used for a single snark
+    /// of `INNER_DEGREE`.
+    pub fn chunk_pk_of(&self, inner_degrees: &[u32]) -> Option<&ProvingKey<G1Affine>> {
+        if inner_degrees == [*INNER_DEGREE] {
+            self.chunk_pk.as_ref()
+        } else {
+            self.agg_pks.get(inner_degrees)
+        }
+    }
+
+    /// Pk of the batch of `num_chunks` chunks of `INNER_DEGREE`.
+    pub fn batch_pk(&self, num_chunks: usize) -> Option<&ProvingKey<G1Affine>> {
+        self.chunk_pk_of(&vec![*INNER_DEGREE; num_chunks])
     }
 
     // Generate the chunk proof given the chunk trace using Poseidon hash for challenges.
@@ -108,6 +185,109 @@ impl Prover {
         self.gen_agg_proof(vec![inner_snark])
     }
 
+    // Same as `gen_chunk_proof`, but with the supplied randomness, e.g. a seeded RNG for an audit
+    // to reproduce the proof bytes. The randomness must be of high quality for production proving,
+    // such as `gen_secure_rng`.
+    pub fn gen_chunk_proof_with_rng(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+        mut rng: impl Rng + Send,
+    ) -> Result<Proof> {
+        let (inner_snark, _) =
+            self.gen_inner_snark_with_rng::<SuperCircuit>(chunk_trace, &mut rng)?;
+        // Compress the inner snark using the aggregation proof.
+        self.gen_agg_proof_with_rng(vec![inner_snark], rng)
+    }
+
+    // Generate the chunk proof, and return the numbers of blocks actually proven alongside. They
+    // could be fewer than the input if the chunk is trimmed by the circuit capacity.
+    pub fn gen_chunk_proof_with_blocks(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+    ) -> Result<(Proof, Vec<u64>)> {
+        let (inner_snark, block_numbers) =
+            self.gen_inner_snark_with_blocks::<SuperCircuit>(chunk_trace)?;
+        let proof = self.gen_agg_proof(vec![inner_snark])?;
+
+        Ok((proof, block_numbers))
+    }
+
+    // Generate the chunk proof, and return the statistics of proof alongside.
+    pub fn gen_chunk_proof_with_stats(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+    ) -> Result<(Proof, ProofStats)> {
+        let now = Instant::now();
+        let inner_snark = self.gen_inner_snark::<SuperCircuit>(chunk_trace)?;
+        let inner_degrees = snark_degrees(std::slice::from_ref(&inner_snark));
+        let proof = self.gen_agg_proof(vec![inner_snark])?;
+        let chunk_pk = self
+            .chunk_pk_of(&inner_degrees)
+            .expect("chunk pk must exist after proving");
+        let stats = ProofStats::new(chunk_pk, &proof, now.elapsed());
+        log::info!("chunk proof stats: {:?}", stats);
+
+        Ok((proof, stats))
+    }
+
+    // Generate the chunk proof, and return it serialized as `{name}_full_proof.json` alongside its
+    // metadata, without creating an output dir or writing any files.
+    pub fn gen_chunk_proof_bytes(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+    ) -> Result<(Vec<u8>, ProofMetadata)> {
+        let proof = self.gen_chunk_proof(chunk_trace)?;
+        let metadata = ProofMetadata::from_proof(&proof);
+
+        Ok((serde_json::to_vec(&proof)?, metadata))
+    }
+
+    // Generate the chunk proof and verify it against the vk of chunk pk.
+    // The proof is returned only if the verification passes.
+    pub fn prove_and_verify_chunk(&mut self, chunk_trace: &[BlockTrace]) -> Result<Proof> {
+        let now = Instant::now();
+        let inner_snark = self.gen_inner_snark::<SuperCircuit>(chunk_trace)?;
+        let inner_degrees = snark_degrees(std::slice::from_ref(&inner_snark));
+        let proof = self.gen_agg_proof(vec![inner_snark])?;
+        log::info!(
+            "finish generating chunk proof, elapsed: {:?}",
+            now.elapsed()
+        );
+
+        let now = Instant::now();
+        let chunk_vk = self
+            .chunk_pk_of(&inner_degrees)
+            .expect("chunk pk must exist after proving")
+            .get_vk();
+        let verified = verify_snark_shplonk::<AggregationCircuit>(
+            &self.chunk_params,
+            proof.to_snark(),
+            chunk_vk,
+        );
+        log::info!(
+            "finish verifying chunk proof: {}, elapsed: {:?}",
+            verified,
+            now.elapsed()
+        );
+
+        if !verified {
+            bail!("chunk proof verification failed");
+        }
+        Ok(proof)
+    }
+
+    // Generate the chunk proof of a single tx of the block trace, for debugging a failed tx.
+    pub fn gen_tx_proof(&mut self, block_trace: &BlockTrace, tx_hash: H256) -> Result<Proof> {
+        let tx_trace = filter_block_trace(block_trace, tx_hash)?;
+        log::info!(
+            "proving tx {:?} of block {:?}",
+            tx_hash,
+            block_trace.header.number
+        );
+
+        self.gen_chunk_proof(&[tx_trace])
+    }
+
     // Generate the chunk proof given the chunk trace using Keccak hash for challenges.
     // The returned proof can be efficiently verified by solidity verifier.
     pub fn gen_chunk_evm_proof(&mut self, chunk_trace: &[BlockTrace]) -> Result<Proof> {
@@ -116,28 +296,116 @@ impl Prover {
         self.gen_agg_evm_proof(vec![inner_snark])
     }
 
+    // Same as `gen_chunk_evm_proof`, but with the supplied randomness.
+    pub fn gen_chunk_evm_proof_with_rng(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+        mut rng: impl Rng + Send,
+    ) -> Result<Proof> {
+        let (inner_snark, _) =
+            self.gen_inner_snark_with_rng::<SuperCircuit>(chunk_trace, &mut rng)?;
+        // Compress the inner snark using the aggregation proof.
+        self.gen_agg_evm_proof_with_rng(vec![inner_snark], rng)
+    }
+
     // Generate the snark of the inner circuit
     pub fn gen_inner_snark<C: TargetCircuit>(
         &mut self,
         chunk_trace: &[BlockTrace],
     ) -> Result<Snark> {
+        Ok(self.gen_inner_snark_with_blocks::<C>(chunk_trace)?.0)
+    }
+
+    // Generate the snark of the inner circuit, and return the block numbers of the final witness.
+    pub fn gen_inner_snark_with_blocks<C: TargetCircuit>(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+    ) -> Result<(Snark, Vec<u64>)> {
+        self.gen_inner_snark_with_rng::<C>(chunk_trace, gen_rng())
+    }
+
+    // Same as `gen_inner_snark_with_blocks`, but with the supplied randomness.
+    pub fn gen_inner_snark_with_rng<C: TargetCircuit>(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+        rng: impl Rng + Send,
+    ) -> Result<(Snark, Vec<u64>)> {
+        self.gen_inner_snark_with_opts::<C>(chunk_trace, &WitnessOptions::default(), rng)
+    }
+
+    fn gen_inner_snark_with_opts<C: TargetCircuit>(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+        opts: &WitnessOptions,
+        mut rng: impl Rng + Send,
+    ) -> Result<(Snark, Vec<u64>)> {
         if chunk_trace.is_empty() {
             bail!("Empty chunk trace");
         }
 
-        let mut block_traces = chunk_trace.to_vec();
-
-        let (circuit, instance) = {
-            // will return early if the check finds out the trace exceeds the circuit capacity
-            check_batch_capacity(&mut block_traces)?;
+        let build_witness = |block_traces: &[BlockTrace]| {
+            let _span = phase_span!("witness");
+            block_traces_to_witness_block_with_opts(block_traces, opts)
+        };
+        let selected_degree = self.select_inner_degree(chunk_trace);
+        let fallback = if selected_degree == *INNER_DEGREE {
+            None
+        } else {
+            // The degree is selected by the estimate, check the real rows of the witness and move
+            // on to the next fallback degree on overflow.
+            let witness_block = build_witness(chunk_trace)?;
+            let rows = calculate_row_usage_of_witness_block(&witness_block)?;
+            match self.fallback_degree_for_rows(&rows, selected_degree) {
+                Some(degree) => {
+                    log::info!("prove chunk with fallback degree {degree}, rows {rows:?}");
+                    Some((degree, witness_block))
+                }
+                None => {
+                    log::warn!(
+                        "chunk of rows {rows:?} exceeds all fallback degrees, prove with degree {}",
+                        *INNER_DEGREE
+                    );
+                    None
+                }
+            }
+        };
 
-            let witness_block = block_traces_to_witness_block(&block_traces)?;
+        let (circuit, instance, block_numbers, degree) = {
+            let (degree, witness_block) = match fallback {
+                Some(fallback) => fallback,
+                None => {
+                    // will return early if the check finds out the trace exceeds the circuit
+                    // capacity
+                    let mut block_traces = chunk_trace.to_vec();
+                    let capacity = batch_capacity_report(&block_traces, CapacityPolicy::Trim)?;
+                    block_traces.truncate(capacity.num_block_kept);
+                    if log::log_enabled!(log::Level::Debug) && !capacity.rows.is_empty() {
+                        let traces = block_traces.iter().cloned().map(Ok);
+                        let estimate = streaming_capacity(traces, *INNER_DEGREE)?;
+                        log::debug!(
+                            "capacity estimate error: {:?}",
+                            capacity_estimate_error(&estimate, &capacity)
+                        );
+                    }
+                    if block_traces.len() < chunk_trace.len() {
+                        log::warn!(
+                            "chunk trimmed from {} to {} blocks",
+                            chunk_trace.len(),
+                            block_traces.len()
+                        );
+                    }
+
+                    (*INNER_DEGREE, build_witness(&block_traces)?)
+                }
+            };
             log::info!(
                 "proving the chunk: {:?}",
                 metric_of_witness_block(&witness_block)
             );
 
-            C::from_witness_block(&witness_block)?
+            let block_numbers: Vec<u64> = witness_block.context.ctxs.keys().copied().collect();
+            let (circuit, instance) = C::from_witness_block(&witness_block)?;
+            (circuit, instance, block_numbers, degree)
         };
 
         // generate the proof for the inner circuit
@@ -149,12 +417,9 @@ impl Prover {
             chunk_trace.len()
         );
 
-        let seed = [0u8; 16];
-        let mut rng = XorShiftRng::from_seed(seed);
-
         if *MOCK_PROVE {
             log::info!("mock prove {} start", C::name());
-            let prover = MockProver::<Fr>::run(*INNER_DEGREE, &circuit, instance)?;
+            let prover = MockProver::<Fr>::run(degree, &circuit, instance)?;
             if let Err(errs) = prover.verify_par() {
                 log::error!("err num: {}", errs.len());
                 for err in &errs {
@@ -165,28 +430,46 @@ impl Prover {
             log::info!("mock prove {} done", C::name());
         }
 
-        if !self.inner_pks.contains_key(&C::name()) {
-            self.gen_inner_pk::<C>(&C::dummy_inner_circuit());
+        let pk_name = inner_pk_name::<C>(degree);
+        if !self.inner_pks.contains_key(&pk_name) {
+            self.gen_inner_pk_of_degree::<C>(&C::dummy_inner_circuit(), degree);
         }
-        let pk = &self.inner_pks[&C::name()];
+        let pk = &self.inner_pks[&pk_name];
 
         // Generate the SNARK proof for the inner circuit
-        let snark_proof =
-            gen_snark_shplonk(&self.inner_params, pk, circuit, &mut rng, None::<String>);
-        Ok(snark_proof)
+        let _span = phase_span!("prove");
+        let params = self.inner_params_of_degree(degree);
+        let snark_proof = gen_snark_shplonk(params, pk, circuit, &mut rng, None::<String>);
+        Ok((snark_proof, block_numbers))
     }
 
     // Generate the aggregation proof given the proofs of inner circuit
     pub fn gen_agg_proof(&mut self, snarks: Vec<Snark>) -> Result<Proof> {
-        // build the aggregation circuit inputs from the inner circuit outputs
-        let seed = [0u8; 16];
-        let mut rng = XorShiftRng::from_seed(seed);
+        self.gen_agg_proof_with_rng(snarks, gen_rng())
+    }
 
+    // Same as `gen_agg_proof`, but with the supplied randomness.
+    pub fn gen_agg_proof_with_rng(
+        &mut self,
+        snarks: Vec<Snark>,
+        mut rng: impl Rng + Send,
+    ) -> Result<Proof> {
+        assert_aggregation_params(&self.inner_params, &self.chunk_params)?;
+
+        // build the aggregation circuit inputs from the inner circuit outputs
+        let inner_degrees = snark_degrees(&snarks);
         let agg_circuit = AggregationCircuit::new(&self.chunk_params, snarks, &mut rng);
-        let chunk_pk = self
-            .chunk_pk
-            .get_or_insert_with(|| gen_pk(&self.chunk_params, &agg_circuit, None));
+        let keygen = || {
+            let _span = phase_span!("keygen");
+            gen_pk(&self.chunk_params, &agg_circuit, None)
+        };
+        let chunk_pk = if inner_degrees == [*INNER_DEGREE] {
+            self.chunk_pk.get_or_insert_with(keygen)
+        } else {
+            self.agg_pks.entry(inner_degrees).or_insert_with(keygen)
+        };
 
+        let _span = phase_span!("prove");
         let agg_proof = gen_snark_shplonk(
             &self.chunk_params,
             chunk_pk,
@@ -200,15 +483,31 @@ impl Prover {
 
     // Generate the aggregation evm proof given the proofs of inner circuit
     pub fn gen_agg_evm_proof(&mut self, snarks: Vec<Snark>) -> Result<Proof> {
-        // build the aggregation circuit inputs from the inner circuit outputs
-        let seed = [0u8; 16];
-        let mut rng = XorShiftRng::from_seed(seed);
+        self.gen_agg_evm_proof_with_rng(snarks, gen_rng())
+    }
 
+    // Same as `gen_agg_evm_proof`, but with the supplied randomness.
+    pub fn gen_agg_evm_proof_with_rng(
+        &mut self,
+        snarks: Vec<Snark>,
+        mut rng: impl Rng + Send,
+    ) -> Result<Proof> {
+        assert_aggregation_params(&self.inner_params, &self.chunk_params)?;
+
+        // build the aggregation circuit inputs from the inner circuit outputs
+        let inner_degrees = snark_degrees(&snarks);
         let agg_circuit = AggregationCircuit::new(&self.chunk_params, snarks, &mut rng);
-        let chunk_pk = self
-            .chunk_pk
-            .get_or_insert_with(|| gen_pk(&self.chunk_params, &agg_circuit, None));
+        let keygen = || {
+            let _span = phase_span!("keygen");
+            gen_pk(&self.chunk_params, &agg_circuit, None)
+        };
+        let chunk_pk = if inner_degrees == [*INNER_DEGREE] {
+            self.chunk_pk.get_or_insert_with(keygen)
+        } else {
+            self.agg_pks.entry(inner_degrees).or_insert_with(keygen)
+        };
 
+        let _span = phase_span!("prove");
         let agg_proof = gen_evm_proof_shplonk(
             &self.chunk_params,
             chunk_pk,
@@ -217,20 +516,100 @@ impl Prover {
             &mut rng,
         );
 
-        Proof::new(
+        let proof = Proof::new(
             chunk_pk,
             agg_proof,
             &agg_circuit.instances(),
             Some(agg_circuit.num_instance()),
-        )
+        )?;
+        try_dump_evm_transcript(&self.chunk_params, chunk_pk.get_vk(), &proof);
+
+        Ok(proof)
     }
 
     // Initiates the public key for a given inner circuit.
     pub(crate) fn gen_inner_pk<C: TargetCircuit>(&mut self, circuit: &<C as TargetCircuit>::Inner) {
-        tick(&format!("before init pk of {}", C::name()));
-        let pk = keygen_pk2(&self.inner_params, circuit)
-            .unwrap_or_else(|e| panic!("failed to generate {} pk: {:?}", C::name(), e));
-        self.inner_pks.insert(C::name(), pk);
-        tick(&format!("after init pk of {}", C::name()));
+        self.gen_inner_pk_of_degree::<C>(circuit, *INNER_DEGREE)
+    }
+
+    fn gen_inner_pk_of_degree<C: TargetCircuit>(
+        &mut self,
+        circuit: &<C as TargetCircuit>::Inner,
+        degree: u32,
+    ) {
+        let _span = phase_span!("keygen");
+        let name = inner_pk_name::<C>(degree);
+        tick(&format!("before init pk of {name}"));
+        let pk = keygen_pk2(self.inner_params_of_degree(degree), circuit)
+            .unwrap_or_else(|e| panic!("failed to generate {name} pk: {:?}", e));
+        self.inner_pks.insert(name.clone(), pk);
+        tick(&format!("after init pk of {name}"));
+    }
+
+    fn inner_params_of_degree(&self, degree: u32) -> &ParamsKZG<Bn256> {
+        if degree == *INNER_DEGREE {
+            &self.inner_params
+        } else {
+            &self.fallback_params[&degree]
+        }
+    }
+
+    // Select the inner degree of the chunk from `INNER_DEGREE` and the degree fallback. Use
+    // `INNER_DEGREE` (and trim the chunk as before) if none fits.
+    fn select_inner_degree(&self, block_traces: &[BlockTrace]) -> u32 {
+        if self.fallback_params.is_empty() {
+            return *INNER_DEGREE;
+        }
+
+        let degrees: Vec<u32> = iter::once(*INNER_DEGREE)
+            .chain(self.fallback_params.keys().copied())
+            .collect();
+        select_degree_for_capacity(block_traces, &degrees).unwrap_or_else(|e| {
+            log::warn!("{e}, prove with degree {}", *INNER_DEGREE);
+            *INNER_DEGREE
+        })
+    }
+
+    // The smallest fallback degree from `min_degree` whose row limits cover the real rows.
+    fn fallback_degree_for_rows(&self, rows: &[usize], min_degree: u32) -> Option<u32> {
+        self.fallback_params
+            .range(min_degree..)
+            .map(|(degree, _)| *degree)
+            .find(|degree| capacity_profile().fits(rows, *degree))
     }
+
+    // Prove the dummy inner circuit with the inner params, to warm up the MSM and FFT code paths of
+    // the real degree. The inner pk is generated and cached along the way.
+    fn warm_up(&mut self) {
+        let now = Instant::now();
+        if !self.inner_pks.contains_key(&SuperCircuit::name()) {
+            self.gen_inner_pk::<SuperCircuit>(&SuperCircuit::dummy_inner_circuit());
+        }
+        let pk = &self.inner_pks[&SuperCircuit::name()];
+
+        gen_snark_shplonk(
+            &self.inner_params,
+            pk,
+            SuperCircuit::dummy_inner_circuit(),
+            &mut gen_rng(),
+            None::<String>,
+        );
+        log::info!("warm-up proof done, elapsed: {:?}", now.elapsed());
+    }
+}
+
+// Inner pks of fallback degrees are keyed as `{name}_{degree}`.
+fn inner_pk_name<C: TargetCircuit>(degree: u32) -> String {
+    if degree == *INNER_DEGREE {
+        C::name()
+    } else {
+        format!("{}_{degree}", C::name())
+    }
+}
+
+fn snark_degrees(snarks: &[Snark]) -> Vec<u32> {
+    snarks
+        .iter()
+        .map(|snark| snark.protocol.domain.k as u32)
+        .collect()
 }
@@ -0,0 +1,196 @@
+use super::{
+    super::circuit::{SuperCircuit, TargetCircuit},
+    inner_pk_name, Prover,
+};
+use crate::{
+    config::INNER_DEGREE,
+    io::{load_snark, read_artifact, try_write_snark, write_artifact},
+    proof::circuit_fingerprint,
+    utils::{assert_aggregation_params, serialize_chunk},
+    Proof,
+};
+use anyhow::{bail, Result};
+use blake2::{Blake2s256, Digest};
+use halo2_proofs::poly::commitment::ParamsProver;
+use serde_derive::{Deserialize, Serialize};
+use snark_verifier_sdk::{verify_snark_shplonk, Snark};
+use std::path::{Path, PathBuf};
+use types::eth::BlockTrace;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct BatchCheckpoint {
+    batch_id: String,
+    chunks: Vec<ChunkCheckpoint>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ChunkCheckpoint {
+    index: usize,
+    fingerprint: String,
+    // Hex of `circuit_fingerprint` of the inner vk proving the chunk, which changes with params,
+    // circuit or pk. Empty for the checkpoints before it, so they are re-proved.
+    #[serde(default)]
+    vk_fingerprint: String,
+}
+
+impl BatchCheckpoint {
+    fn path(output_dir: &str, batch_id: &str) -> PathBuf {
+        Path::new(output_dir).join(format!("{batch_id}.checkpoint"))
+    }
+
+    // Return an empty checkpoint if not exists.
+    fn load(output_dir: &str, batch_id: &str) -> Result<Self> {
+        let path = Self::path(output_dir, batch_id);
+        if !path.exists() {
+            return Ok(Self {
+                batch_id: batch_id.to_string(),
+                chunks: vec![],
+            });
+        }
+
+        let checkpoint: Self = serde_json::from_slice(&read_artifact(&path)?)?;
+        if checkpoint.batch_id != batch_id {
+            bail!(
+                "checkpoint {} is of batch {}",
+                path.display(),
+                checkpoint.batch_id
+            );
+        }
+
+        Ok(checkpoint)
+    }
+
+    fn dump(&self, output_dir: &str) -> Result<()> {
+        let path = Self::path(output_dir, &self.batch_id);
+        write_artifact(&path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    // Return the checkpoint of the chunk if it's done with the same traces.
+    fn done_chunk(&self, index: usize, fingerprint: &str) -> Option<&ChunkCheckpoint> {
+        self.chunks
+            .iter()
+            .find(|c| c.index == index && c.fingerprint == fingerprint)
+    }
+
+    fn mark_done(&mut self, index: usize, fingerprint: String, vk_fingerprint: String) {
+        self.chunks.retain(|c| c.index != index);
+        self.chunks.push(ChunkCheckpoint {
+            index,
+            fingerprint,
+            vk_fingerprint,
+        });
+    }
+}
+
+// Fingerprint of the chunk by its canonical encoding.
+fn chunk_fingerprint(chunk_trace: &[BlockTrace]) -> String {
+    hex::encode(Blake2s256::digest(serialize_chunk(chunk_trace)))
+}
+
+fn snark_path(output_dir: &str, batch_id: &str, index: usize) -> String {
+    Path::new(output_dir)
+        .join(format!("{batch_id}_chunk_{index}.snark"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl Prover {
+    /// Generate the EVM proof of a batch of chunks, which could be resumed after interrupted.
+    /// After each chunk is proved, its snark is saved to `output_dir` and the chunk is recorded in
+    /// `{batch_id}.checkpoint`. On restart, the chunks with matching fingerprints are skipped, if
+    /// their snarks are of the current inner vk and verify.
+    pub fn gen_batch_proof(
+        &mut self,
+        batch_id: &str,
+        chunk_traces: &[Vec<BlockTrace>],
+        output_dir: &str,
+    ) -> Result<Proof> {
+        if chunk_traces.is_empty() {
+            bail!("Empty batch {batch_id}");
+        }
+        // Fail before proving any chunk.
+        assert_aggregation_params(&self.inner_params, &self.chunk_params)?;
+
+        let mut checkpoint = BatchCheckpoint::load(output_dir, batch_id)?;
+        let mut snarks = Vec::with_capacity(chunk_traces.len());
+        for (index, chunk_trace) in chunk_traces.iter().enumerate() {
+            let fingerprint = chunk_fingerprint(chunk_trace);
+            let path = snark_path(output_dir, batch_id, index);
+
+            if let Some(done) = checkpoint.done_chunk(index, &fingerprint) {
+                let vk_fingerprint = done.vk_fingerprint.clone();
+                let snark = load_snark(&path).and_then(|snark| match snark {
+                    Some(snark) => {
+                        self.check_loaded_snark(&snark, &vk_fingerprint)?;
+                        Ok(Some(snark))
+                    }
+                    None => Ok(None),
+                });
+                match snark {
+                    Ok(Some(snark)) => {
+                        log::info!("batch {batch_id}: skip proved chunk {index}");
+                        snarks.push(snark);
+                        continue;
+                    }
+                    Ok(None) => (),
+                    Err(e) => log::warn!(
+                        "batch {batch_id}: failed to load snark of chunk {index}, re-prove it: {e}"
+                    ),
+                }
+            }
+
+            log::info!(
+                "batch {batch_id}: proving chunk {index}/{}",
+                chunk_traces.len()
+            );
+            let snark = self.gen_inner_snark::<SuperCircuit>(chunk_trace)?;
+            let vk_fingerprint = self.inner_vk_fingerprint(snark.protocol.domain.k as u32)?;
+            try_write_snark(&path, &snark)?;
+            checkpoint.mark_done(index, fingerprint, vk_fingerprint);
+            checkpoint.dump(output_dir)?;
+
+            snarks.push(snark);
+        }
+
+        self.gen_agg_evm_proof(snarks)
+    }
+
+    // Hex of `circuit_fingerprint` of the inner vk of degree, the pk is generated if not exists.
+    fn inner_vk_fingerprint(&mut self, degree: u32) -> Result<String> {
+        if degree != *INNER_DEGREE && !self.fallback_params.contains_key(&degree) {
+            bail!("no params of inner degree {degree}");
+        }
+
+        let name = inner_pk_name::<SuperCircuit>(degree);
+        if !self.inner_pks.contains_key(&name) {
+            self.gen_inner_pk_of_degree::<SuperCircuit>(
+                &SuperCircuit::dummy_inner_circuit(),
+                degree,
+            );
+        }
+        Ok(hex::encode(circuit_fingerprint(
+            self.inner_pks[&name].get_vk(),
+        )))
+    }
+
+    // Check the snark loaded from checkpoint is of the current inner vk and verifies, so a stale
+    // snark isn't aggregated after params, circuit or pk change.
+    fn check_loaded_snark(&mut self, snark: &Snark, vk_fingerprint: &str) -> Result<()> {
+        let degree = snark.protocol.domain.k as u32;
+        if self.inner_vk_fingerprint(degree)? != vk_fingerprint {
+            bail!("snark is of a different inner vk");
+        }
+
+        let vk = self.inner_pks[&inner_pk_name::<SuperCircuit>(degree)].get_vk();
+        let params = self.inner_params_of_degree(degree).verifier_params();
+        if !verify_snark_shplonk::<<SuperCircuit as TargetCircuit>::Inner>(
+            params,
+            snark.clone(),
+            vk,
+        ) {
+            bail!("snark verification failed");
+        }
+
+        Ok(())
+    }
+}
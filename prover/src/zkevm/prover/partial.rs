@@ -0,0 +1,51 @@
+use super::{
+    super::circuit::{CircuitSelection, SuperCircuit},
+    Prover,
+};
+use crate::utils::gen_rng;
+use anyhow::Result;
+use snark_verifier_sdk::Snark;
+use types::eth::BlockTrace;
+
+/// Snark of the inner circuit with only the selected sub-circuits assigned. It never verifies as
+/// a full chunk proof, and can't be converted to `Proof` or aggregated.
+#[derive(Debug)]
+pub struct PartialSnark {
+    circuits: CircuitSelection,
+    snark: Snark,
+}
+
+impl PartialSnark {
+    pub fn circuits(&self) -> CircuitSelection {
+        self.circuits
+    }
+
+    pub fn proof(&self) -> &[u8] {
+        &self.snark.proof
+    }
+}
+
+impl Prover {
+    /// Generate the inner snark with the witness of only the selected sub-circuits, to iterate
+    /// faster in circuit development. NEVER use it in production.
+    ///
+    /// Notice that only the witness building of the skipped sub-circuits is saved. The layout of
+    /// `SuperCircuit` is fixed, so the proof is still generated by the full inner pk, over all the
+    /// columns of every sub-circuit, with the skipped ones assigned without their witness.
+    pub fn gen_partial_inner_snark(
+        &mut self,
+        chunk_trace: &[BlockTrace],
+        circuits: CircuitSelection,
+    ) -> Result<PartialSnark> {
+        let opts = circuits.witness_options()?;
+        if !circuits.is_full() {
+            log::warn!(
+                "PARTIAL PROVING with sub-circuits {circuits:?}, the proof is NOT a valid chunk proof"
+            );
+        }
+
+        let (snark, _) =
+            self.gen_inner_snark_with_opts::<SuperCircuit>(chunk_trace, &opts, gen_rng())?;
+        Ok(PartialSnark { circuits, snark })
+    }
+}
@@ -0,0 +1,125 @@
+use super::{
+    circuit::{block_traces_to_witness_block_with_opts, WitnessOptions},
+    gas_check::check_trace_gas_consistency,
+};
+use anyhow::Result;
+use eth_types::H256;
+use types::eth::BlockTrace;
+
+#[derive(Debug, Clone)]
+pub struct ReplayMismatch {
+    // None if the mismatch is of the whole block.
+    pub tx_index: Option<usize>,
+    pub step_index: Option<usize>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOutcome {
+    pub num_tx: usize,
+    pub num_step: usize,
+    // None if the replay failed before reaching the post-state.
+    pub post_state_root: Option<H256>,
+    pub mismatches: Vec<ReplayMismatch>,
+}
+
+impl ReplayOutcome {
+    pub fn is_ok(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Replay a block trace by bus-mapping against the pre-state of its `storage_trace`, without the
+/// circuits, so a malformed trace could be caught before proving.
+/// The state changes of the struct logs are applied to the MPT of the pre-state, and the result
+/// is compared with `root_after`. The success of each tx is compared with its execution result,
+/// and the gas of the struct logs is checked by `check_trace_gas_consistency`.
+pub fn replay_block_trace(trace: &BlockTrace) -> Result<ReplayOutcome> {
+    let gas_outcome = check_trace_gas_consistency(trace)?;
+    let mut outcome = ReplayOutcome {
+        num_tx: gas_outcome.num_tx,
+        num_step: gas_outcome.num_step,
+        post_state_root: None,
+        mismatches: gas_outcome
+            .mismatches
+            .into_iter()
+            .map(|m| ReplayMismatch {
+                tx_index: Some(m.tx_index),
+                step_index: m.step_index,
+                reason: m.reason,
+            })
+            .collect(),
+    };
+
+    let opts = WitnessOptions {
+        build_keccak: false,
+        build_mpt: true,
+    };
+    let block = match block_traces_to_witness_block_with_opts(std::slice::from_ref(trace), &opts) {
+        Ok(block) => block,
+        Err(e) => {
+            outcome.mismatches.push(ReplayMismatch {
+                tx_index: None,
+                step_index: None,
+                reason: format!("replay failed: {e:#}"),
+            });
+            return Ok(outcome);
+        }
+    };
+
+    if block.txs.len() != trace.execution_results.len() {
+        outcome.mismatches.push(ReplayMismatch {
+            tx_index: None,
+            step_index: None,
+            reason: format!(
+                "replayed {} txs, but {} execution results",
+                block.txs.len(),
+                trace.execution_results.len()
+            ),
+        });
+    }
+    for (tx_index, (tx, result)) in block
+        .txs
+        .iter()
+        .zip(trace.execution_results.iter())
+        .enumerate()
+    {
+        let is_success = tx.calls.first().map_or(false, |call| call.is_success);
+        if is_success == result.failed {
+            outcome.mismatches.push(ReplayMismatch {
+                tx_index: Some(tx_index),
+                step_index: None,
+                reason: format!(
+                    "replayed tx success {is_success}, but execution result failed {}",
+                    result.failed
+                ),
+            });
+        }
+    }
+
+    let mut post_state_root = H256::zero();
+    block
+        .mpt_updates
+        .new_root()
+        .to_big_endian(post_state_root.as_bytes_mut());
+    if post_state_root != trace.storage_trace.root_after {
+        outcome.mismatches.push(ReplayMismatch {
+            tx_index: None,
+            step_index: None,
+            reason: format!(
+                "replayed post-state root {post_state_root:?}, but root_after {:?}",
+                trace.storage_trace.root_after
+            ),
+        });
+    }
+    outcome.post_state_root = Some(post_state_root);
+
+    log::debug!(
+        "replay of block trace {:?}: {} tx, {} steps, {} mismatches",
+        trace.header.number,
+        outcome.num_tx,
+        outcome.num_step,
+        outcome.mismatches.len()
+    );
+    Ok(outcome)
+}
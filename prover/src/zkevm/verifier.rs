@@ -1,12 +1,18 @@
 use super::circuit::TargetCircuit;
 use crate::{
     config::{CHUNK_DEGREE, INNER_DEGREE},
+    io::deserialize_vk,
+    proof::{check_proof_vk_compatible, CHUNK_ACC_LEN},
     utils::load_params,
     Proof,
 };
 use anyhow::{bail, Result};
 use halo2_proofs::{
-    halo2curves::bn256::{Bn256, G1Affine},
+    halo2curves::{
+        bn256::{Bn256, Fq, Fr, G1Affine},
+        pairing::Engine,
+        CurveAffine,
+    },
     plonk::{keygen_vk, verify_proof, VerifyingKey},
     poly::{
         commitment::{Params, ParamsProver},
@@ -17,9 +23,11 @@ use halo2_proofs::{
     SerdeFormat,
 };
 use itertools::Itertools;
-use snark_verifier::system::halo2::transcript::evm::EvmTranscript;
-use snark_verifier_sdk::{verify_snark_shplonk, AggregationCircuit, Snark};
-use std::{collections::HashMap, io::Cursor};
+use snark_verifier::{
+    system::halo2::transcript::evm::EvmTranscript, util::arithmetic::fe_from_limbs,
+};
+use snark_verifier_sdk::{verify_snark_shplonk, AggregationCircuit, Snark, BITS, LIMBS};
+use std::collections::HashMap;
 
 pub struct Verifier {
     inner_params: ParamsKZG<Bn256>,
@@ -35,11 +43,7 @@ impl Verifier {
         raw_chunk_vk: Option<Vec<u8>>,
     ) -> Self {
         let chunk_vk = raw_chunk_vk.as_ref().map(|k| {
-            VerifyingKey::<G1Affine>::read::<_, AggregationCircuit>(
-                &mut Cursor::new(&k),
-                SerdeFormat::Processed,
-            )
-            .unwrap()
+            deserialize_vk::<AggregationCircuit>(k, Some(SerdeFormat::Processed)).unwrap()
         });
 
         Self {
@@ -72,6 +76,7 @@ impl Verifier {
             Some(vk) => vk,
             None => panic!("Chunk verification key is missing"),
         };
+        check_proof_vk_compatible(&proof, chunk_vk)?;
 
         Ok(verify_snark_shplonk::<AggregationCircuit>(
             &self.chunk_params,
@@ -85,6 +90,7 @@ impl Verifier {
             Some(vk) => vk,
             None => panic!("Chunk verification key is missing"),
         };
+        check_proof_vk_compatible(&proof, chunk_vk)?;
 
         let mut transcript = TranscriptReadBuffer::<_, G1Affine, _>::init(proof.proof());
 
@@ -117,3 +123,149 @@ impl Verifier {
         }
     }
 }
+
+/// Verify the EVM proof of a batch, i.e. of `Prover::gen_batch_proof`, and its KZG accumulator.
+pub fn verify_batch_proof(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    batch_proof: &Proof,
+) -> Result<bool> {
+    Ok(verify_batch_proof_with_inputs(params, vk, batch_proof)?.0)
+}
+
+/// Same as `verify_batch_proof`, but also return the aggregated public inputs after the
+/// accumulator, for the caller to cross-check them against the expected ones.
+pub fn verify_batch_proof_with_inputs(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    batch_proof: &Proof,
+) -> Result<(bool, Vec<Fr>)> {
+    let instances = batch_proof.instances();
+    if instances.len() != 1 {
+        bail!(
+            "batch proof of {} instance columns, expected 1",
+            instances.len()
+        );
+    }
+    let instances = &instances[0];
+    if instances.len() < CHUNK_ACC_LEN {
+        bail!(
+            "batch proof of {} instances, less than the accumulator len {CHUNK_ACC_LEN}",
+            instances.len()
+        );
+    }
+
+    let column = [instances.as_slice()];
+    let mut transcript = TranscriptReadBuffer::<_, G1Affine, _>::init(batch_proof.proof());
+    let proof_verified = VerificationStrategy::<_, VerifierSHPLONK<Bn256>>::finalize(
+        verify_proof::<_, VerifierSHPLONK<Bn256>, _, EvmTranscript<_, _, _, _>, _>(
+            params,
+            vk,
+            AccumulatorStrategy::new(params),
+            &[&column[..]],
+            &mut transcript,
+        )
+        .map_err(|e| anyhow::format_err!("failed to verify batch proof: {e:?}"))?,
+    );
+    if !proof_verified {
+        log::error!("batch proof verification failed");
+    }
+
+    let accumulator_verified = verify_accumulator(params, &instances[..CHUNK_ACC_LEN])?;
+    if !accumulator_verified {
+        log::error!("batch proof accumulator verification failed");
+    }
+
+    Ok((
+        proof_verified && accumulator_verified,
+        instances[CHUNK_ACC_LEN..].to_vec(),
+    ))
+}
+
+// The accumulator (lhs, rhs) is encoded as the limbs of lhs.x, lhs.y, rhs.x and rhs.y, and is
+// valid if e(lhs, g2) = e(rhs, s_g2).
+fn verify_accumulator(params: &ParamsKZG<Bn256>, limbs: &[Fr]) -> Result<bool> {
+    let coordinates: Vec<Fq> = limbs
+        .chunks(LIMBS)
+        .map(|limbs| fe_from_limbs::<_, Fq, LIMBS, BITS>(limbs.try_into().unwrap()))
+        .collect();
+    let point = |x: Fq, y: Fq| -> Result<G1Affine> {
+        Option::from(G1Affine::from_xy(x, y))
+            .ok_or_else(|| anyhow::format_err!("batch proof accumulator is not on curve"))
+    };
+    let lhs = point(coordinates[0], coordinates[1])?;
+    let rhs = point(coordinates[2], coordinates[3])?;
+
+    Ok(Bn256::pairing(&lhs, &params.g2()) == Bn256::pairing(&rhs, &params.s_g2()))
+}
+
+/// Verify the EVM chunk proofs by accumulating their openings into a single pairing check. If the
+/// combined check fails, the proofs are verified one by one to find the failed ones, so a bad proof
+/// doesn't reject the others.
+pub fn batch_verify_chunk_proofs(
+    params: &ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    proofs: &[Proof],
+) -> Result<Vec<bool>> {
+    let accumulated = proofs
+        .iter()
+        .try_fold(AccumulatorStrategy::new(params), |strategy, proof| {
+            accumulate_evm_proof(params, vk, strategy, proof)
+        });
+    if let Ok(strategy) = accumulated {
+        if VerificationStrategy::<_, VerifierSHPLONK<Bn256>>::finalize(strategy) {
+            return Ok(vec![true; proofs.len()]);
+        }
+    }
+
+    log::warn!(
+        "batch verification of {} proofs failed, verify them one by one",
+        proofs.len()
+    );
+    let results = proofs
+        .iter()
+        .enumerate()
+        .map(|(i, proof)| {
+            match accumulate_evm_proof(params, vk, AccumulatorStrategy::new(params), proof) {
+                Ok(strategy) => {
+                    VerificationStrategy::<_, VerifierSHPLONK<Bn256>>::finalize(strategy)
+                }
+                Err(e) => {
+                    log::error!("failed to verify proof {i}: {e:?}");
+                    false
+                }
+            }
+        })
+        .collect();
+
+    Ok(results)
+}
+
+// Verify the proof with the accumulated openings of the previous proofs, without the final pairing
+// check.
+fn accumulate_evm_proof<'a>(
+    params: &'a ParamsKZG<Bn256>,
+    vk: &VerifyingKey<G1Affine>,
+    strategy: AccumulatorStrategy<'a, Bn256>,
+    proof: &Proof,
+) -> Result<AccumulatorStrategy<'a, Bn256>> {
+    check_proof_vk_compatible(proof, vk)?;
+
+    let instances = proof.instances();
+    let instances = instances.iter().map(|ins| ins.as_slice()).collect_vec();
+    let mut transcript = TranscriptReadBuffer::<_, G1Affine, _>::init(proof.proof());
+
+    Ok(verify_proof::<
+        _,
+        VerifierSHPLONK<Bn256>,
+        _,
+        EvmTranscript<_, _, _, _>,
+        _,
+    >(
+        params,
+        vk,
+        strategy,
+        &[instances.as_slice()],
+        &mut transcript,
+    )?)
+}
@@ -4,7 +4,7 @@ use prover::{
     utils::init_env_and_log,
     zkevm::{
         circuit::{SuperCircuit, TargetCircuit},
-        Prover,
+        verify_batch_proof_with_inputs, Prover,
     },
     EvmVerifier,
 };
@@ -96,3 +96,48 @@ fn test_chunk_prove_verify() {
     EvmVerifier::new(deployment_code).verify(agg_circuit.instances(), chunk_proof.proof().to_vec());
     log::info!("end to end test completed");
 }
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_batch_prove_verify() {
+    let output_dir = init_env_and_log("batch_tests");
+    let block_traces = load_block_traces_for_test().1;
+
+    let mut prover = Prover::from_params_dir(PARAMS_DIR);
+    let batch_proof = prover
+        .gen_batch_proof("test", &[block_traces.clone(), block_traces], &output_dir)
+        .unwrap();
+
+    let batch_vk = prover.batch_pk(2).unwrap().get_vk();
+    let (verified, public_inputs) =
+        verify_batch_proof_with_inputs(&prover.chunk_params, batch_vk, &batch_proof).unwrap();
+    assert!(verified);
+    log::info!(
+        "verified batch proof of {} public inputs",
+        public_inputs.len()
+    );
+}
+
+#[cfg(feature = "prove_verify")]
+#[test]
+fn test_chunk_then_batch_prove_verify() {
+    let output_dir = init_env_and_log("chunk_then_batch_tests");
+    let block_traces = load_block_traces_for_test().1;
+
+    // The chunk and the batch are proved by the same prover, with different pks.
+    let mut prover = Prover::from_params_dir(PARAMS_DIR);
+    let chunk_proof = prover.gen_chunk_evm_proof(&block_traces).unwrap();
+    let batch_proof = prover
+        .gen_batch_proof("test", &[block_traces.clone(), block_traces], &output_dir)
+        .unwrap();
+
+    let chunk_vk = prover.chunk_pk.as_ref().unwrap().get_vk();
+    let (verified, _) =
+        verify_batch_proof_with_inputs(&prover.chunk_params, chunk_vk, &chunk_proof).unwrap();
+    assert!(verified);
+
+    let batch_vk = prover.batch_pk(2).unwrap().get_vk();
+    let (verified, _) =
+        verify_batch_proof_with_inputs(&prover.chunk_params, batch_vk, &batch_proof).unwrap();
+    assert!(verified);
+}
@@ -57,7 +57,7 @@ fn test_comp_prove_verify() {
     log::info!("Got compression EVM proof (layer-2)");
 
     // Construct verifier and EVM verify.
-    let params = prover.params(*AGG_LAYER2_DEGREE).clone();
+    let params = prover.params(*AGG_LAYER2_DEGREE).unwrap().clone();
     let vk = prover.pk("agg_layer2").unwrap().get_vk().clone();
     let verifier = Verifier::new(params, Some(vk));
     let yul_file_path = format!("{output_dir}/comp_verifier.yul");
@@ -1,13 +1,36 @@
 use chrono::Utc;
-use halo2_proofs::{plonk::keygen_vk, SerdeFormat};
+use halo2_proofs::{
+    arithmetic::best_multiexp,
+    halo2curves::{
+        bn256::Fr,
+        group::{ff::PrimeField, Curve},
+    },
+    plonk::keygen_vk,
+    poly::{
+        commitment::{Blind, Params},
+        EvaluationDomain,
+    },
+    SerdeFormat,
+};
 use prover::{
     config::INNER_DEGREE,
     io::serialize_vk,
-    test_util::{load_block_traces_for_test, parse_trace_path_from_mode, PARAMS_DIR},
-    utils::{get_block_trace_from_file, init_env_and_log, load_params},
+    proof::{decode_chunk_instances, ChunkInstances, CHUNK_ACC_LEN, CHUNK_DIGEST_LEN},
+    test_util::{
+        gen_insecure_test_params, load_block_traces_for_test, parse_trace_path_from_mode,
+        PARAMS_DIR,
+    },
+    utils::{
+        compute_g_lagrange, deserialize_chunk, expected_public_inputs, get_block_trace_from_file,
+        init_env_and_log, load_params, public_input_hash, serialize_chunk, truncate_block_trace,
+        validate_chunk_continuity, verify_params_commitment, verify_roundtrip, ContinuityError,
+    },
     zkevm::{
-        circuit::{SuperCircuit, TargetCircuit},
-        CircuitCapacityChecker, Prover, Verifier,
+        circuit::{
+            block_traces_to_witness_block, calibrate_witness_memory, SuperCircuit, TargetCircuit,
+            WitnessMemoryCoefficients,
+        },
+        replay_block_trace, CircuitCapacityChecker, Prover, Verifier,
     },
 };
 
@@ -38,6 +61,178 @@ fn test_load_params() {
     .unwrap();
 }
 
+#[test]
+fn test_gen_insecure_test_params() {
+    init_env_and_log("integration");
+
+    let params = gen_insecure_test_params(8);
+    assert_eq!(params.k(), 8);
+
+    verify_params_commitment(&params, params.s_g2()).unwrap();
+}
+
+#[test]
+fn test_chunk_encoding() {
+    init_env_and_log("integration");
+    let trace_path = parse_trace_path_from_mode("multiswap");
+    let chunk = vec![get_block_trace_from_file(trace_path)];
+
+    let buf = serialize_chunk(&chunk);
+    let decoded = deserialize_chunk(&buf).unwrap();
+    assert_eq!(decoded.len(), chunk.len());
+    assert_eq!(serialize_chunk(&decoded), buf);
+}
+
+#[test]
+fn test_truncate_block_trace() {
+    init_env_and_log("integration");
+    let trace = get_block_trace_from_file(parse_trace_path_from_mode("multiswap"));
+
+    let truncated = truncate_block_trace(&trace, 1).unwrap();
+    assert_eq!(truncated.transactions.len(), 1);
+    assert_eq!(truncated.header.transactions.len(), 1);
+    assert_eq!(
+        truncated.header.gas_used,
+        truncated.execution_results[0].gas.into()
+    );
+    assert_eq!(
+        truncated.storage_trace.root_after,
+        trace.tx_storage_trace[0].root_after
+    );
+    let proofs = truncated.storage_trace.proofs.unwrap_or_default();
+    let all_proofs = trace.storage_trace.proofs.unwrap_or_default();
+    assert!(proofs
+        .keys()
+        .all(|address| all_proofs.contains_key(address)));
+}
+
+#[test]
+fn test_chunk_instances_encoding() {
+    let instances = ChunkInstances {
+        accumulator: (0..CHUNK_ACC_LEN as u64).map(Fr::from).collect(),
+        pi_hash: std::array::from_fn(|i| (i * 7) as u8),
+    };
+
+    let encoded = instances.encode();
+    assert_eq!(encoded.len(), CHUNK_ACC_LEN + CHUNK_DIGEST_LEN);
+    assert_eq!(decode_chunk_instances(&encoded).unwrap(), instances);
+
+    let mut invalid = encoded;
+    invalid[CHUNK_ACC_LEN] = Fr::from(256);
+    assert!(decode_chunk_instances(&invalid).is_err());
+}
+
+#[test]
+fn test_compute_g_lagrange() {
+    let params = gen_insecure_test_params(5);
+    let mut downsized = params.clone();
+    downsized.downsize(4);
+
+    for target in [&params, &downsized] {
+        let degree = target.k();
+        let g_lagrange = compute_g_lagrange(params.get_g(), degree);
+
+        // Commitments in lagrange basis must match the ones of params.
+        let mut poly = EvaluationDomain::<Fr>::new(1, degree).empty_lagrange();
+        for (i, v) in poly.iter_mut().enumerate() {
+            *v = Fr::from(i as u64 + 1);
+        }
+        let expected = target.commit_lagrange(&poly, Blind::default()).to_affine();
+        assert_eq!(best_multiexp(&poly, &g_lagrange).to_affine(), expected);
+    }
+}
+
+#[test]
+fn test_witness_roundtrip() {
+    init_env_and_log("integration");
+    for mode in ["single", "multiple", "native", "dao", "nft"] {
+        let trace = get_block_trace_from_file(parse_trace_path_from_mode(mode));
+        verify_roundtrip(&[trace]).unwrap();
+    }
+}
+
+#[test]
+fn test_calibrate_witness_memory() {
+    init_env_and_log("integration");
+    let samples: Vec<_> = ["single", "multiple", "dao"]
+        .into_iter()
+        .map(|mode| vec![get_block_trace_from_file(parse_trace_path_from_mode(mode))])
+        .collect();
+
+    let coefficients = calibrate_witness_memory(&samples).unwrap();
+    log::info!("witness memory coefficients: {coefficients:?}");
+    assert!(coefficients.per_block > 0);
+
+    let path = std::env::temp_dir().join("witness_memory_coefficients.json");
+    coefficients.dump(&path).unwrap();
+    assert_eq!(
+        WitnessMemoryCoefficients::from_json_file(&path).unwrap(),
+        coefficients
+    );
+}
+
+#[test]
+fn test_replay_block_trace() {
+    init_env_and_log("integration");
+    for mode in ["single", "multiple", "native", "dao", "nft"] {
+        let trace = get_block_trace_from_file(parse_trace_path_from_mode(mode));
+        let outcome = replay_block_trace(&trace).unwrap();
+        assert!(outcome.is_ok(), "{mode}: {:?}", outcome.mismatches);
+        assert_eq!(
+            outcome.post_state_root,
+            Some(trace.storage_trace.root_after)
+        );
+    }
+}
+
+#[test]
+fn test_expected_public_inputs() {
+    init_env_and_log("integration");
+    for mode in ["single", "multiple", "native", "dao", "nft"] {
+        let block_traces = vec![get_block_trace_from_file(parse_trace_path_from_mode(mode))];
+
+        // Match the instances of the circuit of a full witness build.
+        let expected = expected_public_inputs(&block_traces).unwrap();
+        let (_, instances) = SuperCircuit::from_block_traces(&block_traces).unwrap();
+        assert_eq!(expected, instances, "public inputs mismatch of mode {mode}");
+
+        // The high and low halves are the big-endian bytes of the pi hash, the same as
+        // `ChunkInstances::pi_hash` of the chunk proof.
+        let pi_hash = public_input_hash(&block_traces_to_witness_block(&block_traces).unwrap());
+        let halves: Vec<u8> = expected[0]
+            .iter()
+            .flat_map(|f| f.to_repr()[..16].iter().rev().copied().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(halves, pi_hash);
+    }
+}
+
+#[test]
+fn test_chunk_continuity() {
+    init_env_and_log("integration");
+    let trace = get_block_trace_from_file(parse_trace_path_from_mode("single"));
+    let chunk = |numbers: &[u64]| -> Vec<_> {
+        numbers
+            .iter()
+            .map(|n| {
+                let mut trace = trace.clone();
+                trace.header.number = Some((*n).into());
+                trace
+            })
+            .collect()
+    };
+
+    assert_eq!(validate_chunk_continuity(&chunk(&[5, 6, 7])), Ok(()));
+    assert_eq!(
+        validate_chunk_continuity(&chunk(&[5, 6, 6, 7])),
+        Err(ContinuityError::Duplicate(6))
+    );
+    assert_eq!(
+        validate_chunk_continuity(&chunk(&[5, 7])),
+        Err(ContinuityError::Gap { prev: 5, next: 7 })
+    );
+}
+
 #[test]
 fn test_capacity_checker() {
     init_env_and_log("integration");
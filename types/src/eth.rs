@@ -51,14 +51,17 @@ pub struct TransactionTrace {
     pub tx_hash: H256,
     #[serde(rename = "type")]
     pub type_: u8,
+    #[serde(deserialize_with = "crate::num::deserialize_u64")]
     pub nonce: u64,
+    #[serde(deserialize_with = "crate::num::deserialize_u64")]
     pub gas: u64,
-    #[serde(rename = "gasPrice")]
+    #[serde(rename = "gasPrice", deserialize_with = "crate::num::deserialize_u256")]
     pub gas_price: U256,
     pub from: Address,
     pub to: Option<Address>,
-    #[serde(rename = "chainId")]
+    #[serde(rename = "chainId", deserialize_with = "crate::num::deserialize_u256")]
     pub chain_id: U256,
+    #[serde(deserialize_with = "crate::num::deserialize_u256")]
     pub value: U256,
     pub data: Bytes,
     #[serde(rename = "isCreate")]
@@ -120,8 +123,13 @@ pub type EthBlock = Block<Transaction>;
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct ExecutionResult {
-    #[serde(rename = "l1DataFee", default)]
+    #[serde(
+        rename = "l1DataFee",
+        default,
+        deserialize_with = "crate::num::deserialize_u256"
+    )]
     pub l1_fee: U256,
+    #[serde(deserialize_with = "crate::num::deserialize_u64")]
     pub gas: u64,
     pub failed: bool,
     #[serde(rename = "returnValue", default)]
@@ -221,7 +229,9 @@ impl ExtraData {
 #[derive(Serialize, Deserialize, Clone, Default, Debug)]
 pub struct AccountProofWrapper {
     pub address: Option<Address>,
+    #[serde(default, deserialize_with = "crate::num::deserialize_opt_u64")]
     pub nonce: Option<u64>,
+    #[serde(default, deserialize_with = "crate::num::deserialize_opt_u256")]
     pub balance: Option<U256>,
     #[serde(rename = "keccakCodeHash")]
     pub keccak_code_hash: Option<H256>,
@@ -233,7 +243,9 @@ pub struct AccountProofWrapper {
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct StorageProofWrapper {
+    #[serde(default, deserialize_with = "crate::num::deserialize_opt_u256")]
     pub key: Option<U256>,
+    #[serde(default, deserialize_with = "crate::num::deserialize_opt_u256")]
     pub value: Option<U256>,
     pub proof: Option<Vec<Bytes>>,
 }
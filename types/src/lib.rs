@@ -19,3 +19,81 @@ pub mod base64 {
         decode(s.as_bytes()).map_err(serde::de::Error::custom)
     }
 }
+
+/// Tolerant deserialization of integers, which accepts JSON numbers, decimal strings and
+/// 0x-prefixed hex strings. Serialization is kept as is.
+///
+/// Notice that JSON numbers exceeding `u64` are rejected, since they could not be parsed without
+/// precision loss, use strings instead.
+pub mod num {
+    use ethers_core::types::U256;
+    use serde::{
+        de::{self, Visitor},
+        Deserialize, Deserializer,
+    };
+    use std::fmt;
+
+    fn parse_u256(s: &str) -> Result<U256, String> {
+        match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => U256::from_str_radix(hex, 16).map_err(|e| format!("{e:?}")),
+            None => U256::from_dec_str(s).map_err(|e| format!("{e:?}")),
+        }
+        .map_err(|e| format!("invalid number {s:?}: {e}"))
+    }
+
+    struct U256Visitor;
+
+    impl<'de> Visitor<'de> for U256Visitor {
+        type Value = U256;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a number, a decimal string or a 0x-prefixed hex string")
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<U256, E> {
+            Ok(U256::from(v))
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<U256, E> {
+            u64::try_from(v)
+                .map(U256::from)
+                .map_err(|_| E::custom(format!("negative number {v}")))
+        }
+
+        fn visit_f64<E: de::Error>(self, v: f64) -> Result<U256, E> {
+            Err(E::custom(format!(
+                "number {v} is not an exact integer, use a string instead"
+            )))
+        }
+
+        fn visit_str<E: de::Error>(self, v: &str) -> Result<U256, E> {
+            parse_u256(v).map_err(E::custom)
+        }
+    }
+
+    pub fn deserialize_u256<'de, D: Deserializer<'de>>(d: D) -> Result<U256, D::Error> {
+        d.deserialize_any(U256Visitor)
+    }
+
+    pub fn deserialize_u64<'de, D: Deserializer<'de>>(d: D) -> Result<u64, D::Error> {
+        let v = deserialize_u256(d)?;
+        if v > U256::from(u64::MAX) {
+            return Err(de::Error::custom(format!("number {v} exceeds u64")));
+        }
+        Ok(v.as_u64())
+    }
+
+    pub fn deserialize_opt_u256<'de, D: Deserializer<'de>>(d: D) -> Result<Option<U256>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(deserialize_with = "deserialize_u256")] U256);
+
+        Option::<Wrapper>::deserialize(d).map(|w| w.map(|w| w.0))
+    }
+
+    pub fn deserialize_opt_u64<'de, D: Deserializer<'de>>(d: D) -> Result<Option<u64>, D::Error> {
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(deserialize_with = "deserialize_u64")] u64);
+
+        Option::<Wrapper>::deserialize(d).map(|w| w.map(|w| w.0))
+    }
+}